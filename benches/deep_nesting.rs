@@ -0,0 +1,62 @@
+// parses documents of growing nesting depth and prints the time
+// per element, which stays flat as long as parsing is linear
+//
+// run with: cargo bench --bench deep_nesting
+
+extern crate novaxml;
+
+use std::time::{Duration, Instant};
+
+use novaxml::Document;
+use novaxml::arena::ArenaDocument;
+
+const RUNS: usize = 5;
+
+fn nested(depth: usize) -> String {
+    let mut s = String::with_capacity(depth * 20);
+    for i in 0..depth {
+        s.push_str(&format!("<e i=\"{}\">", i));
+    }
+    for _ in 0..depth {
+        s.push_str("</e>");
+    }
+    s
+}
+
+// fastest of a few runs, to keep noise out
+fn measure<F: FnMut()>(mut f: F) -> Duration {
+    let mut best = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        f();
+        let elapsed = start.elapsed();
+        best = Some(match best {
+            Some(b) if b < elapsed => b,
+            _ => elapsed,
+        });
+    }
+    best.unwrap_or_default()
+}
+
+fn main() {
+    println!("{:>8} {:>14} {:>14} {:>14} {:>14}",
+             "depth", "document", "ns/element", "arena", "ns/element");
+
+    let mut depth = 1000;
+    while depth <= 32000 {
+        let xml = nested(depth);
+        let document = measure(|| {
+            Document::from_string(&xml).unwrap();
+        });
+        let arena = measure(|| {
+            ArenaDocument::from_string(&xml).unwrap();
+        });
+        println!("{:>8} {:>12.2?} {:>14} {:>12.2?} {:>14}",
+                 depth,
+                 document,
+                 document.as_nanos() / depth as u128,
+                 arena,
+                 arena.as_nanos() / depth as u128);
+        depth *= 2;
+    }
+}
@@ -0,0 +1,666 @@
+use std::fmt;
+use std::io::{BufReader, Read};
+use std::slice;
+
+use super::error::Error;
+use super::namespace::QName;
+use super::nodes::*;
+use super::parser::ParserConfig;
+use super::reader::Reader;
+use super::sax::{self, ContentHandler};
+use super::tokenizer::*;
+use super::Document;
+
+// handle of a node in an ArenaDocument, it stays
+// valid for as long as the document exists
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+// name and attributes of an element in an ArenaDocument,
+// its children are linked through the document
+#[derive(Clone, Debug, PartialEq)]
+pub struct ElementData {
+    name: QName,
+    attributes: Vec<Attribute>,
+    empty: bool,
+}
+
+impl ElementData {
+    pub fn new(q: QName) -> ElementData {
+        ElementData {
+            name: q,
+            attributes: Vec::new(),
+            empty: false,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        self.name.get_name()
+    }
+
+    pub fn get_qname(&self) -> &QName {
+        &self.name
+    }
+
+    pub fn set_qname(&mut self, q: QName) {
+        self.name = q;
+    }
+
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    pub fn attributes_mut(&mut self) -> &mut Vec<Attribute> {
+        &mut self.attributes
+    }
+
+    pub fn get_attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|x| x.get_name() == name)
+    }
+
+    // changes the value of the attribute named name,
+    // or appends it if there is none
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        match self.attributes.iter_mut().find(|x| x.get_name() == name) {
+            Some(a) => a.set_value(value),
+            None => self.attributes.push(Attribute::new(name, value)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+
+    pub fn set_empty(&mut self, b: bool) {
+        self.empty = b;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeData {
+    // parent of the nodes at the top level, every
+    // ArenaDocument has exactly one
+    Document,
+    Element(ElementData),
+    Text(String),
+    Comment(String),
+    CData(String),
+    ProcessingInstruction(ProcessingInstruction),
+}
+
+impl NodeData {
+    pub fn as_element(&self) -> Option<&ElementData> {
+        match *self {
+            NodeData::Element(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    pub fn as_element_mut(&mut self) -> Option<&mut ElementData> {
+        match *self {
+            NodeData::Element(ref mut e) => Some(e),
+            _ => None,
+        }
+    }
+
+    // returns the character data of text and CDATA nodes
+    pub fn as_text(&self) -> Option<&str> {
+        match *self {
+            NodeData::Text(ref s) | NodeData::CData(ref s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    data: NodeData,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+}
+
+// a document whose nodes are stored in one vector and linked
+// to their parent and siblings, so navigating and moving nodes
+// is cheap, detached nodes are kept until the document is dropped
+#[derive(Clone, Debug)]
+pub struct ArenaDocument {
+    nodes: Vec<Entry>,
+    declaration: Option<Declaration>,
+    doctype: Option<Doctype>,
+    // number of top level nodes before the doctype
+    doctype_index: usize,
+}
+
+impl Default for ArenaDocument {
+    fn default() -> ArenaDocument {
+        ArenaDocument::new()
+    }
+}
+
+impl fmt::Display for ArenaDocument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.to_document().fmt(f)
+    }
+}
+
+impl ArenaDocument {
+    pub fn new() -> ArenaDocument {
+        let mut d = ArenaDocument {
+            nodes: Vec::new(),
+            declaration: None,
+            doctype: None,
+            doctype_index: 0,
+        };
+        d.new_node(NodeData::Document);
+        d
+    }
+
+    pub fn from_string(s: &str) -> Result<ArenaDocument, Error> {
+        ArenaDocument::from_string_with(s, &ParserConfig::default())
+    }
+
+    pub fn from_string_with(s: &str,
+                            config: &ParserConfig) -> Result<ArenaDocument, Error> {
+        let mut reader = Reader::with_config(Tokenizer::new(str_chars(s)), config.clone());
+        let mut builder = ArenaBuilder::new();
+        sax::parse(&mut reader, &mut builder)?;
+        Ok(builder.document)
+    }
+
+    pub fn from_reader<R: Read>(r: R) -> Result<ArenaDocument, Error> {
+        ArenaDocument::from_reader_with(r, &ParserConfig::default())
+    }
+
+    pub fn from_reader_with<R: Read>(r: R,
+                                     config: &ParserConfig) -> Result<ArenaDocument, Error> {
+        let chars = CharReader::new(BufReader::new(r));
+        let mut reader = Reader::with_config(Tokenizer::new(chars), config.clone());
+        let mut builder = ArenaBuilder::new();
+        sax::parse(&mut reader, &mut builder)?;
+        Ok(builder.document)
+    }
+
+    // copies an owned tree into a new arena
+    pub fn from_document(d: &Document) -> ArenaDocument {
+        let mut arena = ArenaDocument::new();
+        arena.declaration = d.declaration.clone();
+        arena.doctype = d.doctype.clone();
+        arena.doctype_index = d.doctype_index;
+
+        let document = arena.document();
+        let mut stack: Vec<(NodeId, slice::Iter<Node>)> = vec![(document, d.root.children.iter())];
+        while let Some(&mut (parent, ref mut children)) = stack.last_mut() {
+            let n = match children.next() {
+                Some(n) => n,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+            let data = match *n {
+                Node::Element(ref e) => {
+                    NodeData::Element(ElementData {
+                        name: e.name.clone(),
+                        attributes: e.attributes.clone(),
+                        empty: e.empty,
+                    })
+                }
+                Node::Text(ref s) => NodeData::Text(s.clone()),
+                Node::Comment(ref s) => NodeData::Comment(s.clone()),
+                Node::CData(ref s) => NodeData::CData(s.clone()),
+                Node::ProcessingInstruction(ref pi) => NodeData::ProcessingInstruction(pi.clone()),
+            };
+            let id = arena.new_node(data);
+            arena.append(parent, id);
+            if let Node::Element(ref e) = *n {
+                stack.push((id, e.children.iter()));
+            }
+        }
+        arena
+    }
+
+    // copies the nodes attached to the document into an owned tree
+    pub fn to_document(&self) -> Document {
+        let mut d = Document::new();
+        d.declaration = self.declaration.clone();
+        d.doctype = self.doctype.clone();
+        d.doctype_index = self.doctype_index;
+        for id in self.children(self.document()) {
+            d.root.append_node(self.to_node(id));
+        }
+        d
+    }
+
+    // copies the subtree at id into an owned element,
+    // returns None if id isn't an element
+    pub fn to_element(&self, id: NodeId) -> Option<Element> {
+        self.element(id)?;
+        match self.to_node(id) {
+            Node::Element(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn to_node(&self, id: NodeId) -> Node {
+        // open elements, the root of the subtree
+        // stays at the bottom of the stack
+        let mut stack: Vec<Element> = Vec::new();
+        for edge in self.traverse(id) {
+            match edge {
+                Edge::Start(n) => {
+                    let node = match self.get(n) {
+                        NodeData::Element(ref e) => {
+                            let mut element = Element::from_qname(e.name.clone());
+                            element.set_attributes(e.attributes.clone());
+                            element.set_empty(e.empty);
+                            stack.push(element);
+                            continue;
+                        }
+                        NodeData::Document => continue,
+                        NodeData::Text(ref s) => Node::Text(s.clone()),
+                        NodeData::Comment(ref s) => Node::Comment(s.clone()),
+                        NodeData::CData(ref s) => Node::CData(s.clone()),
+                        NodeData::ProcessingInstruction(ref pi) => {
+                            Node::ProcessingInstruction(pi.clone())
+                        }
+                    };
+                    match stack.last_mut() {
+                        Some(parent) => parent.append_node(node),
+                        None => return node,
+                    }
+                }
+                Edge::End(n) => {
+                    if self.get(n).as_element().is_none() {
+                        continue;
+                    }
+                    let element = match stack.pop() {
+                        Some(e) => e,
+                        None => break,
+                    };
+                    match stack.last_mut() {
+                        Some(parent) => parent.append_child(element),
+                        None => return Node::Element(element),
+                    }
+                }
+            }
+        }
+        Node::Element(Element::new_empty())
+    }
+
+    // the node holding the top level nodes
+    pub fn document(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    // the first element at the top level
+    pub fn root(&self) -> Option<NodeId> {
+        self.children(self.document()).find(|&id| self.get(id).as_element().is_some())
+    }
+
+    pub fn declaration(&self) -> Option<&Declaration> {
+        self.declaration.as_ref()
+    }
+
+    pub fn set_declaration(&mut self, declaration: Option<Declaration>) {
+        self.declaration = declaration;
+    }
+
+    pub fn doctype(&self) -> Option<&Doctype> {
+        self.doctype.as_ref()
+    }
+
+    pub fn set_doctype(&mut self, doctype: Option<Doctype>) {
+        self.doctype = doctype;
+    }
+
+    // number of nodes, including detached ones
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() <= 1
+    }
+
+    pub fn get(&self, id: NodeId) -> &NodeData {
+        &self.nodes[id.0].data
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> &mut NodeData {
+        &mut self.nodes[id.0].data
+    }
+
+    pub fn element(&self, id: NodeId) -> Option<&ElementData> {
+        self.get(id).as_element()
+    }
+
+    pub fn element_mut(&mut self, id: NodeId) -> Option<&mut ElementData> {
+        self.get_mut(id).as_element_mut()
+    }
+
+    // the concatenated character data of the children of id
+    pub fn text(&self, id: NodeId) -> String {
+        let mut text = String::new();
+        for child in self.children(id) {
+            if let Some(s) = self.get(child).as_text() {
+                text.push_str(s);
+            }
+        }
+        text
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].parent
+    }
+
+    pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].first_child
+    }
+
+    pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].last_child
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].next_sibling
+    }
+
+    pub fn previous_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].previous_sibling
+    }
+
+    pub fn children(&self, id: NodeId) -> Children<'_> {
+        Children {
+            document: self,
+            next: self.first_child(id),
+        }
+    }
+
+    // the parent of id, its parent and so on,
+    // up to the document node
+    pub fn ancestors(&self, id: NodeId) -> Ancestors<'_> {
+        Ancestors {
+            document: self,
+            next: self.parent(id),
+        }
+    }
+
+    // id and the nodes below it as Start and End edges,
+    // in the order they appear in the document
+    pub fn traverse(&self, id: NodeId) -> Traverse<'_> {
+        Traverse {
+            document: self,
+            root: id,
+            next: Some(Edge::Start(id)),
+        }
+    }
+
+    // all nodes below id, depth-first in document order
+    pub fn descendants(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self.traverse(id)
+            .filter_map(|edge| match edge {
+                Edge::Start(n) => Some(n),
+                Edge::End(_) => None,
+            })
+            .skip(1)
+    }
+
+    // creates a node that isn't attached anywhere yet
+    pub fn new_node(&mut self, data: NodeData) -> NodeId {
+        assert!(self.nodes.is_empty() || data != NodeData::Document,
+                "an ArenaDocument has only one document node");
+        self.nodes.push(Entry {
+            data,
+            parent: None,
+            first_child: None,
+            last_child: None,
+            previous_sibling: None,
+            next_sibling: None,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    pub fn new_element(&mut self, name: &str) -> NodeId {
+        self.new_node(NodeData::Element(ElementData::new(QName::new(name))))
+    }
+
+    pub fn new_element_ns(&mut self, uri: &str, name: &str) -> NodeId {
+        self.new_node(NodeData::Element(ElementData::new(QName::new_ns(uri, name))))
+    }
+
+    pub fn new_text(&mut self, text: &str) -> NodeId {
+        self.new_node(NodeData::Text(text.to_string()))
+    }
+
+    // unlinks id from its parent and siblings, its
+    // children stay attached to it
+    pub fn detach(&mut self, id: NodeId) {
+        let (parent, previous, next) = {
+            let e = &self.nodes[id.0];
+            (e.parent, e.previous_sibling, e.next_sibling)
+        };
+        match previous {
+            Some(p) => self.nodes[p.0].next_sibling = next,
+            None => {
+                if let Some(parent) = parent {
+                    self.nodes[parent.0].first_child = next;
+                }
+            }
+        }
+        match next {
+            Some(n) => self.nodes[n.0].previous_sibling = previous,
+            None => {
+                if let Some(parent) = parent {
+                    self.nodes[parent.0].last_child = previous;
+                }
+            }
+        }
+        let e = &mut self.nodes[id.0];
+        e.parent = None;
+        e.previous_sibling = None;
+        e.next_sibling = None;
+    }
+
+    // moves child to the end of the children of parent
+    pub fn append(&mut self, parent: NodeId, child: NodeId) {
+        let last = self.last_child(parent);
+        self.attach(child, parent, last, None);
+    }
+
+    // moves child to the start of the children of parent
+    pub fn prepend(&mut self, parent: NodeId, child: NodeId) {
+        let first = self.first_child(parent);
+        self.attach(child, parent, None, first);
+    }
+
+    // moves n right before sibling, which must have a parent
+    pub fn insert_before(&mut self, sibling: NodeId, n: NodeId) {
+        let parent = self.parent(sibling)
+            .expect("insert_before: sibling has no parent");
+        let previous = self.previous_sibling(sibling);
+        self.attach(n, parent, previous, Some(sibling));
+    }
+
+    // moves n right after sibling, which must have a parent
+    pub fn insert_after(&mut self, sibling: NodeId, n: NodeId) {
+        let parent = self.parent(sibling)
+            .expect("insert_after: sibling has no parent");
+        let next = self.next_sibling(sibling);
+        self.attach(n, parent, Some(sibling), next);
+    }
+
+    // links n between previous and next, both children of parent
+    fn attach(&mut self,
+              n: NodeId,
+              parent: NodeId,
+              previous: Option<NodeId>,
+              next: Option<NodeId>) {
+        assert!(n != self.document(), "the document node can't be moved");
+        // only nodes with children can contain parent, the
+        // check walks up from parent so leaves skip it
+        let inside = n == parent
+            || (self.first_child(n).is_some() && self.ancestors(parent).any(|a| a == n));
+        assert!(!inside, "a node can't be moved into itself");
+        if previous == Some(n) || next == Some(n) {
+            return;
+        }
+
+        self.detach(n);
+        match previous {
+            Some(p) => self.nodes[p.0].next_sibling = Some(n),
+            None => self.nodes[parent.0].first_child = Some(n),
+        }
+        match next {
+            Some(s) => self.nodes[s.0].previous_sibling = Some(n),
+            None => self.nodes[parent.0].last_child = Some(n),
+        }
+        let e = &mut self.nodes[n.0];
+        e.parent = Some(parent);
+        e.previous_sibling = previous;
+        e.next_sibling = next;
+    }
+}
+
+pub struct Children<'a> {
+    document: &'a ArenaDocument,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.document.next_sibling(id);
+        Some(id)
+    }
+}
+
+pub struct Ancestors<'a> {
+    document: &'a ArenaDocument,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.document.parent(id);
+        Some(id)
+    }
+}
+
+// a node being entered or left during a traversal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Start(NodeId),
+    End(NodeId),
+}
+
+// depth-first traversal following the links
+// between nodes, without a stack
+pub struct Traverse<'a> {
+    document: &'a ArenaDocument,
+    root: NodeId,
+    next: Option<Edge>,
+}
+
+impl<'a> Iterator for Traverse<'a> {
+    type Item = Edge;
+
+    fn next(&mut self) -> Option<Edge> {
+        let edge = self.next?;
+        self.next = match edge {
+            Edge::Start(id) => match self.document.first_child(id) {
+                Some(child) => Some(Edge::Start(child)),
+                None => Some(Edge::End(id)),
+            },
+            Edge::End(id) if id == self.root => None,
+            Edge::End(id) => match self.document.next_sibling(id) {
+                Some(sibling) => Some(Edge::Start(sibling)),
+                None => self.document.parent(id).map(Edge::End),
+            },
+        };
+        Some(edge)
+    }
+}
+
+// content handler that builds an ArenaDocument
+struct ArenaBuilder {
+    document: ArenaDocument,
+    current: NodeId,
+}
+
+impl ArenaBuilder {
+    fn new() -> ArenaBuilder {
+        let document = ArenaDocument::new();
+        let current = document.document();
+        ArenaBuilder {
+            document,
+            current,
+        }
+    }
+
+    fn append(&mut self, data: NodeData) -> NodeId {
+        let id = self.document.new_node(data);
+        self.document.append(self.current, id);
+        id
+    }
+}
+
+impl ContentHandler for ArenaBuilder {
+    fn start_element(&mut self,
+                     name: &QName,
+                     attributes: &[Attribute],
+                     empty: bool) {
+        let id = self.append(NodeData::Element(ElementData {
+            name: name.clone(),
+            attributes: attributes.to_vec(),
+            empty,
+        }));
+        self.current = id;
+    }
+
+    fn end_element(&mut self, _name: &QName) {
+        if let Some(parent) = self.document.parent(self.current) {
+            self.current = parent;
+        }
+    }
+
+    fn characters(&mut self, text: &str) {
+        // text is merged with a directly preceding text node
+        if let Some(last) = self.document.last_child(self.current) {
+            if let NodeData::Text(ref mut s) = *self.document.get_mut(last) {
+                s.push_str(text);
+                return;
+            }
+        }
+        self.append(NodeData::Text(text.to_string()));
+    }
+
+    fn cdata(&mut self, text: &str) {
+        self.append(NodeData::CData(text.to_string()));
+    }
+
+    fn comment(&mut self, text: &str) {
+        self.append(NodeData::Comment(text.to_string()));
+    }
+
+    fn processing_instruction(&mut self, target: &str, data: &str) {
+        self.append(NodeData::ProcessingInstruction(ProcessingInstruction::new(target, data)));
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) {
+        self.document.declaration = Some(declaration.clone());
+    }
+
+    fn doctype(&mut self, doctype: &Doctype) {
+        let document = self.document.document();
+        self.document.doctype = Some(doctype.clone());
+        self.document.doctype_index = self.document.children(document).count();
+    }
+}
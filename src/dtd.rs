@@ -0,0 +1,312 @@
+use super::entities::*;
+use super::error::{Error, Position};
+use super::parser::ParserConfig;
+use super::tokenizer::{is_name_char, is_name_start_char, is_whitespace};
+
+// reads the declarations of an internal DTD subset,
+// only entity declarations are kept, everything
+// else is skipped
+pub fn parse_internal_subset(s: &str,
+                             position: Position,
+                             config: &ParserConfig) -> Result<Entities, Error> {
+    let mut entities = Entities::new();
+    let mut expanded = 0;
+    let mut parser = SubsetParser {
+        config,
+        entities: &mut entities,
+        expanded: &mut expanded,
+        open: Vec::new(),
+    };
+    parser.declarations(s, position)?;
+    Ok(entities)
+}
+
+// position and remaining characters of the subset
+struct Cursor<'s> {
+    s: &'s str,
+    position: Position,
+}
+
+impl<'s> Cursor<'s> {
+    fn peek(&self) -> Option<char> {
+        self.s.chars().next()
+    }
+
+    fn starts_with(&self, prefix: &str) -> bool {
+        self.s.starts_with(prefix)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.s = &self.s[c.len_utf8()..];
+        self.position.advance(c, c.len_utf8());
+        Some(c)
+    }
+
+    fn skip(&mut self, n: usize) {
+        for _ in 0..n {
+            self.next();
+        }
+    }
+
+    // returns whether any whitespace was skipped
+    fn skip_whitespace(&mut self) -> bool {
+        let mut skipped = false;
+        while self.peek().is_some_and(is_whitespace) {
+            self.next();
+            skipped = true;
+        }
+        skipped
+    }
+
+    fn require_whitespace(&mut self) -> Result<(), Error> {
+        if !self.skip_whitespace() {
+            return Err(self.unexpected());
+        }
+        Ok(())
+    }
+
+    // skips past the next occurrence of end
+    fn skip_past(&mut self, end: &str) -> Result<(), Error> {
+        while !self.starts_with(end) {
+            if self.next().is_none() {
+                return Err(Error::UnexpectedEof(self.position));
+            }
+        }
+        self.skip(end.chars().count());
+        Ok(())
+    }
+
+    fn read_name(&mut self) -> Result<String, Error> {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if is_whitespace(c) || "<>\"'%;[]".contains(c) {
+                break;
+            }
+            let valid = if name.is_empty() { is_name_start_char(c) } else { is_name_char(c) };
+            if !valid {
+                return Err(Error::InvalidCharacter(c, self.position));
+            }
+            name.push(c);
+            self.next();
+        }
+        if name.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(name)
+    }
+
+    fn read_quoted(&mut self) -> Result<String, Error> {
+        let quote = match self.peek() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => return Err(self.unexpected()),
+        };
+        self.next();
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some(c) if c == quote => return Ok(value),
+                Some(c) => value.push(c),
+                None => return Err(Error::UnexpectedEof(self.position)),
+            }
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        if self.peek() != Some(c) {
+            return Err(self.unexpected());
+        }
+        self.next();
+        Ok(())
+    }
+
+    fn unexpected(&self) -> Error {
+        match self.peek() {
+            Some(c) => Error::InvalidCharacter(c, self.position),
+            None => Error::UnexpectedEof(self.position),
+        }
+    }
+}
+
+struct SubsetParser<'a> {
+    config: &'a ParserConfig,
+    entities: &'a mut Entities,
+    // bytes of references to parameter entities
+    // and the characters they produced
+    expanded: &'a mut usize,
+    // parameter entities being expanded
+    open: Vec<String>,
+}
+
+impl<'a> SubsetParser<'a> {
+    fn declarations(&mut self, s: &str, position: Position) -> Result<(), Error> {
+        let mut cursor = Cursor {
+            s,
+            position,
+        };
+
+        loop {
+            cursor.skip_whitespace();
+            if cursor.peek().is_none() {
+                return Ok(());
+            }
+
+            if cursor.starts_with("<!ENTITY") {
+                cursor.skip("<!ENTITY".len());
+                self.entity_declaration(&mut cursor)?;
+            }
+            else if cursor.starts_with("<!--") {
+                cursor.skip(4);
+                cursor.skip_past("-->")?;
+            }
+            else if cursor.starts_with("<?") {
+                cursor.skip(2);
+                cursor.skip_past("?>")?;
+            }
+            else if cursor.starts_with("<!") {
+                // element, attribute list and notation
+                // declarations are not used
+                skip_declaration(&mut cursor)?;
+            }
+            else if cursor.peek() == Some('%') {
+                self.parameter_reference(&mut cursor)?;
+            }
+            else {
+                return Err(cursor.unexpected());
+            }
+        }
+    }
+
+    // <!ENTITY [% ]name "value"> or <!ENTITY [% ]name SYSTEM "uri">
+    fn entity_declaration(&mut self, cursor: &mut Cursor) -> Result<(), Error> {
+        cursor.require_whitespace()?;
+        let parameter = cursor.peek() == Some('%');
+        if parameter {
+            cursor.next();
+            cursor.require_whitespace()?;
+        }
+        let name = cursor.read_name()?;
+        cursor.require_whitespace()?;
+
+        let entity = match cursor.peek() {
+            Some('"') | Some('\'') => {
+                let position = cursor.position;
+                let value = cursor.read_quoted()?;
+                if value.contains('%') {
+                    // parameter entities can't be used inside
+                    // declarations in the internal subset
+                    return Err(Error::Syntax(
+                        "Parameter entity reference in entity value".into(),
+                        position));
+                }
+                Entity::Internal(decode_characters(&value, position)?)
+            }
+            _ => {
+                let (public_id, system_id) = read_external_id(cursor)?;
+                cursor.skip_whitespace();
+                if !parameter && cursor.starts_with("NDATA") {
+                    cursor.skip("NDATA".len());
+                    cursor.require_whitespace()?;
+                    cursor.read_name()?;
+                }
+                Entity::External {
+                    public_id,
+                    system_id,
+                }
+            }
+        };
+
+        cursor.skip_whitespace();
+        cursor.expect('>')?;
+
+        if parameter {
+            self.entities.declare_parameter(&name, entity);
+        }
+        else {
+            self.entities.declare_general(&name, entity);
+        }
+        Ok(())
+    }
+
+    // %name; between declarations, its replacement
+    // text is read as more declarations
+    fn parameter_reference(&mut self, cursor: &mut Cursor) -> Result<(), Error> {
+        let position = cursor.position;
+        cursor.next();
+        let name = cursor.read_name()?;
+        cursor.expect(';')?;
+
+        let text = match self.entities.get_parameter(&name) {
+            Some(Entity::Internal(text)) => text.clone(),
+            // external parameter entities are never loaded
+            Some(Entity::External { .. }) => return Ok(()),
+            None => {
+                return Err(Error::InvalidReference(
+                    format!("Undefined parameter entity: %{};", name),
+                    position));
+            }
+        };
+
+        if self.open.contains(&name) {
+            return Err(Error::InvalidReference(
+                format!("Recursive parameter entity: %{};", name),
+                position));
+        }
+        if self.open.len() >= self.config.max_entity_depth {
+            return Err(Error::LimitExceeded(
+                format!("Entity nesting deeper than {}", self.config.max_entity_depth),
+                position));
+        }
+        // counting the reference too, as for general entities
+        *self.expanded += text.len() + name.len() + 2;
+        if *self.expanded > self.config.max_entity_expansion {
+            return Err(Error::LimitExceeded(
+                format!("Entities expanded to more than {} bytes",
+                        self.config.max_entity_expansion),
+                position));
+        }
+
+        self.open.push(name);
+        self.declarations(&text, position)?;
+        self.open.pop();
+        Ok(())
+    }
+}
+
+// SYSTEM "uri" or PUBLIC "id" "uri"
+fn read_external_id(cursor: &mut Cursor) -> Result<(Option<String>, String), Error> {
+    if cursor.starts_with("SYSTEM") {
+        cursor.skip("SYSTEM".len());
+        cursor.require_whitespace()?;
+        return Ok((None, cursor.read_quoted()?));
+    }
+    if cursor.starts_with("PUBLIC") {
+        cursor.skip("PUBLIC".len());
+        cursor.require_whitespace()?;
+        let public_id = cursor.read_quoted()?;
+        cursor.require_whitespace()?;
+        return Ok((Some(public_id), cursor.read_quoted()?));
+    }
+    Err(cursor.unexpected())
+}
+
+// skips a markup declaration, including
+// any '>' inside quoted strings
+fn skip_declaration(cursor: &mut Cursor) -> Result<(), Error> {
+    cursor.skip(2);
+    loop {
+        match cursor.peek() {
+            Some('>') => {
+                cursor.next();
+                return Ok(());
+            }
+            Some('"') | Some('\'') => {
+                cursor.read_quoted()?;
+            }
+            Some(_) => {
+                cursor.next();
+            }
+            None => return Err(Error::UnexpectedEof(cursor.position)),
+        }
+    }
+}
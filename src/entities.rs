@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+
+use super::error::{Error, Position};
+use super::parser::ParserConfig;
+use super::tokenizer::{is_name, is_whitespace};
+
+// value of a predefined entity
+pub fn predefined(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ => None,
+    }
+}
+
+// characters allowed in XML documents
+pub fn is_xml_char(c: char) -> bool {
+    matches!(c,
+             '\u{9}' | '\u{a}' | '\u{d}'
+             | '\u{20}'..='\u{d7ff}'
+             | '\u{e000}'..='\u{fffd}'
+             | '\u{10000}'..='\u{10ffff}')
+}
+
+// resolves the text between '&' and ';'
+pub fn resolve(reference: &str,
+               position: Position) -> Result<char, Error> {
+    let number = match reference.strip_prefix('#') {
+        Some(number) => number,
+        None => {
+            return match predefined(reference) {
+                Some(c) => Ok(c),
+                None => Err(Error::InvalidReference(
+                    format!("Undefined entity: &{};", reference),
+                    position)),
+            };
+        }
+    };
+
+    let (digits, radix) = match number.strip_prefix('x') {
+        Some(hex) => (hex, 16),
+        None => (number, 10),
+    };
+    let valid = !digits.is_empty()
+        && digits.chars().all(|c| c.is_digit(radix));
+    if !valid {
+        return Err(Error::InvalidReference(
+            format!("Invalid character reference: &{};", reference),
+            position));
+    }
+
+    let code = u32::from_str_radix(digits, radix).ok();
+    match code.and_then(::std::char::from_u32) {
+        Some(c) if is_xml_char(c) => Ok(c),
+        _ => Err(Error::InvalidReference(
+            format!("Character out of range: &{};", reference),
+            position)),
+    }
+}
+
+// reads a reference from chars, after its '&'
+fn read_reference<I: Iterator<Item = char>>(chars: &mut I,
+                                            position: Position) -> Result<String, Error> {
+    let mut reference = String::new();
+    loop {
+        match chars.next() {
+            Some(';') => return Ok(reference),
+            Some(c) => reference.push(c),
+            None => {
+                return Err(Error::InvalidReference(
+                    format!("Unterminated reference: &{}", reference),
+                    position));
+            }
+        }
+    }
+}
+
+fn skip_reference(position: &mut Position, reference: &str) {
+    position.offset += reference.len() + 2;
+    position.column += reference.chars().count() + 2;
+}
+
+// replaces character references in s, leaving
+// entity references as they are, as is done
+// for the values of entity declarations
+pub fn decode_characters(s: &str, position: Position) -> Result<String, Error> {
+    let mut decoded = String::with_capacity(s.len());
+    let mut position = position;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '&' {
+            decoded.push(c);
+            position.advance(c, c.len_utf8());
+            continue;
+        }
+
+        let reference = read_reference(&mut chars, position)?;
+        if reference.starts_with('#') {
+            decoded.push(resolve(&reference, position)?);
+        }
+        else if !is_name(&reference) {
+            return Err(Error::InvalidReference(
+                format!("Invalid entity reference: &{};", reference),
+                position));
+        }
+        else {
+            decoded.push('&');
+            decoded.push_str(&reference);
+            decoded.push(';');
+        }
+        skip_reference(&mut position, &reference);
+    }
+    Ok(decoded)
+}
+
+// replaces entity and character references in s,
+// position is where s starts in the source
+pub fn decode(s: &str, position: Position) -> Result<String, Error> {
+    let entities = Entities::new();
+    let config = ParserConfig::default();
+    let mut expanded = 0;
+    Expander::new(&entities, &config, &mut expanded).text(s, position)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Entity {
+    // replacement text, with character
+    // references already replaced
+    Internal(String),
+    // entity in another file, these are never loaded
+    External {
+        public_id: Option<String>,
+        system_id: String,
+    },
+}
+
+// entities declared in a DTD
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Entities {
+    general: HashMap<String, Entity>,
+    parameter: HashMap<String, Entity>,
+}
+
+impl Entities {
+    pub fn new() -> Entities {
+        Entities::default()
+    }
+
+    // the first declaration of an entity is binding,
+    // returns false if name was declared already
+    pub fn declare_general(&mut self, name: &str, e: Entity) -> bool {
+        if self.general.contains_key(name) {
+            return false;
+        }
+        self.general.insert(name.to_string(), e);
+        true
+    }
+
+    pub fn declare_parameter(&mut self, name: &str, e: Entity) -> bool {
+        if self.parameter.contains_key(name) {
+            return false;
+        }
+        self.parameter.insert(name.to_string(), e);
+        true
+    }
+
+    pub fn get_general(&self, name: &str) -> Option<&Entity> {
+        self.general.get(name)
+    }
+
+    pub fn get_parameter(&self, name: &str) -> Option<&Entity> {
+        self.parameter.get(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.general.is_empty() && self.parameter.is_empty()
+    }
+}
+
+// expands references in text and attribute values,
+// counting every reference and every character produced
+// by an entity against the limits of the parser configuration,
+// replacement text is never parsed as markup
+pub struct Expander<'a> {
+    entities: &'a Entities,
+    config: &'a ParserConfig,
+    // bytes of references expanded and characters produced
+    // by entities so far, shared by all expansions in a document
+    expanded: &'a mut usize,
+    in_attribute: bool,
+    // entities being expanded, to detect recursion
+    open: Vec<String>,
+}
+
+impl<'a> Expander<'a> {
+    pub fn new(entities: &'a Entities,
+               config: &'a ParserConfig,
+               expanded: &'a mut usize) -> Expander<'a> {
+        Expander {
+            entities,
+            config,
+            expanded,
+            in_attribute: false,
+            open: Vec::new(),
+        }
+    }
+
+    pub fn text(&mut self, s: &str, position: Position) -> Result<String, Error> {
+        self.in_attribute = false;
+        self.expand(s, position)
+    }
+
+    // whitespace in attribute values is normalized to spaces,
+    // replacement text too, which also must not contain '<'
+    pub fn attribute(&mut self, s: &str, position: Position) -> Result<String, Error> {
+        self.in_attribute = true;
+        self.expand(s, position)
+    }
+
+    fn expand(&mut self, s: &str, position: Position) -> Result<String, Error> {
+        if !s.contains('&') {
+            if self.in_attribute {
+                return Ok(s.chars().map(|c| self.normalize(c)).collect());
+            }
+            return Ok(s.to_string());
+        }
+        let mut out = String::with_capacity(s.len());
+        self.expand_into(&mut out, s, position, 0)?;
+        Ok(out)
+    }
+
+    // position only advances at depth 0, errors in
+    // replacement text point at the outermost reference
+    fn expand_into(&mut self,
+                   out: &mut String,
+                   s: &str,
+                   position: Position,
+                   depth: usize) -> Result<(), Error> {
+        let mut position = position;
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '&' {
+                if depth == 0 {
+                    out.push(self.normalize(c));
+                    position.advance(c, c.len_utf8());
+                    continue;
+                }
+                if self.in_attribute && c == '<' {
+                    return Err(Error::InvalidCharacter(c, position));
+                }
+                self.count(c.len_utf8(), position)?;
+                out.push(self.normalize(c));
+                continue;
+            }
+
+            let reference = read_reference(&mut chars, position)?;
+            if reference.starts_with('#') || predefined(&reference).is_some() {
+                let c = resolve(&reference, position)?;
+                if depth > 0 {
+                    self.count(c.len_utf8(), position)?;
+                }
+                out.push(c);
+            }
+            else {
+                self.expand_entity(out, &reference, position, depth)?;
+            }
+            if depth == 0 {
+                skip_reference(&mut position, &reference);
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_entity(&mut self,
+                     out: &mut String,
+                     name: &str,
+                     position: Position,
+                     depth: usize) -> Result<(), Error> {
+        let entities = self.entities;
+        let text = match entities.get_general(name) {
+            Some(Entity::Internal(text)) => text,
+            Some(Entity::External { .. }) => {
+                return Err(Error::InvalidReference(
+                    format!("External entity not supported: &{};", name),
+                    position));
+            }
+            None => {
+                return Err(Error::InvalidReference(
+                    format!("Undefined entity: &{};", name),
+                    position));
+            }
+        };
+
+        if self.open.iter().any(|open| open == name) {
+            return Err(Error::InvalidReference(
+                format!("Recursive entity: &{};", name),
+                position));
+        }
+        // replacement text is only expanded as character data, not
+        // parsed, so markup in it would silently turn into text
+        if !self.in_attribute && text.contains('<') {
+            return Err(Error::InvalidReference(
+                format!("Markup in entity replacement text not supported: &{};", name),
+                position));
+        }
+        if depth >= self.config.max_entity_depth {
+            return Err(Error::LimitExceeded(
+                format!("Entity nesting deeper than {}", self.config.max_entity_depth),
+                position));
+        }
+
+        // the reference itself is counted too, otherwise entities
+        // expanding to nothing could be referenced endlessly
+        self.count(name.len() + 2, position)?;
+
+        self.open.push(name.to_string());
+        self.expand_into(out, text, position, depth + 1)?;
+        self.open.pop();
+        Ok(())
+    }
+
+    // character references aren't normalized,
+    // they are pushed as they are
+    fn normalize(&self, c: char) -> char {
+        if self.in_attribute && is_whitespace(c) {
+            return ' ';
+        }
+        c
+    }
+
+    fn count(&mut self, len: usize, position: Position) -> Result<(), Error> {
+        *self.expanded += len;
+        if *self.expanded > self.config.max_entity_expansion {
+            return Err(Error::LimitExceeded(
+                format!("Entities expanded to more than {} bytes",
+                        self.config.max_entity_expansion),
+                position));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+// a location in the source, line and column
+// start at 1 and count characters, the offset
+// counts bytes from the start of the input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+}
+
+impl Position {
+    pub fn new() -> Position {
+        Position {
+            line: 1,
+            column: 1,
+            offset: 0,
+        }
+    }
+
+    // moves the position past c, which took
+    // len bytes in the source
+    pub fn advance(&mut self, c: char, len: usize) {
+        self.offset += len;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        }
+        else {
+            self.column += 1;
+        }
+    }
+}
+
+impl Default for Position {
+    fn default() -> Position {
+        Position::new()
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Syntax(String, Position),
+    MismatchedTag {
+        expected: String,
+        found: String,
+        position: Position,
+    },
+    UnexpectedEof(Position),
+    InvalidCharacter(char, Position),
+    InvalidReference(String, Position),
+    LimitExceeded(String, Position),
+    UndeclaredPrefix(String, Position),
+}
+
+impl Error {
+    pub fn position(&self) -> Option<Position> {
+        match *self {
+            Error::Io(_) => None,
+            Error::Syntax(_, p)
+            | Error::MismatchedTag { position: p, .. }
+            | Error::UnexpectedEof(p)
+            | Error::InvalidCharacter(_, p)
+            | Error::InvalidReference(_, p)
+            | Error::LimitExceeded(_, p)
+            | Error::UndeclaredPrefix(_, p) => Some(p),
+        }
+    }
+
+    // renders the line of source the error occurred on,
+    // with a caret pointing at the error position:
+    //
+    //  3 | <a><b></a>
+    //    |         ^
+    pub fn render_source(&self, source: &str) -> Option<String> {
+        let position = self.position()?;
+        let line = source.lines().nth(position.line - 1).unwrap_or("");
+        let number = position.line.to_string();
+
+        let mut caret = String::new();
+        for c in line.chars().take(position.column - 1) {
+            caret.push(if c == '\t' { '\t' } else { ' ' });
+        }
+        caret.push('^');
+
+        Some(format!("{} | {}\n{} | {}",
+                     number,
+                     line,
+                     " ".repeat(number.len()),
+                     caret))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Syntax(ref s, p)
+            | Error::InvalidReference(ref s, p)
+            | Error::LimitExceeded(ref s, p) => write!(f, "{} at {}", s, p),
+            Error::MismatchedTag { ref expected, ref found, position } => {
+                write!(f, "Expected closing tag: {}, found closing tag: {} at {}",
+                       expected,
+                       found,
+                       position)
+            }
+            Error::UnexpectedEof(p) => {
+                write!(f, "Unexpected end of file at {}", p)
+            }
+            Error::InvalidCharacter(c, p) => {
+                write!(f, "Invalid character {:?} at {}", c, p)
+            }
+            Error::UndeclaredPrefix(ref s, p) => {
+                write!(f, "Undeclared namespace prefix: {} at {}", s, p)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
@@ -1,54 +1,107 @@
+pub mod arena;
+pub mod dtd;
+pub mod entities;
+pub mod error;
+pub mod namespace;
 pub mod nodes;
 pub mod parser;
+pub mod reader;
+pub mod sax;
 pub mod tokenizer;
+pub mod traversal;
+pub mod writer;
 
 mod tests;
 
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str;
+
+pub use error::{Error, Position};
+
 use tokenizer::*;
 use parser::*;
 use nodes::*;
+use writer::*;
 
 pub struct Document {
     root: Element,
+    declaration: Option<Declaration>,
+    doctype: Option<Doctype>,
+    // number of top level nodes before the doctype
+    doctype_index: usize,
+}
+
+impl Default for Document {
+    fn default() -> Document {
+        Document::new()
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut buf = Vec::new();
+        if self.write_to(&mut buf).is_err() {
+            return Err(fmt::Error);
+        }
+        match str::from_utf8(&buf) {
+            Ok(s) => f.write_str(s),
+            Err(_) => Err(fmt::Error),
+        }
+    }
 }
 
 impl Document {
     pub fn new() -> Document {
         Document {
             root: Element::new("root"),
+            declaration: None,
+            doctype: None,
+            doctype_index: 0,
         }
     }
 
     pub fn from_element(e: Element) -> Document {
         Document {
             root: e,
+            declaration: None,
+            doctype: None,
+            doctype_index: 0,
         }
     }
 
-    pub fn from_string(s: &str) -> Result<Document, String> {
-        let tokens = match tokenize(&s) {
-            Ok(tokens) => tokens,
-            Err(e) => return Err(e),
-        };
+    pub fn from_string(s: &str) -> Result<Document, Error> {
+        Document::from_string_with(s, &ParserConfig::default())
+    }
 
-        let element = match parse(tokens) {
-            Ok(element) => element,
-            Err(e) => return Err(e),
-        };
+    pub fn from_string_with(s: &str,
+                            config: &ParserConfig) -> Result<Document, Error> {
+        parse_document_with(Tokenizer::new(str_chars(s)), config)
+    }
 
-        Ok(Document::from_element(element))
+    pub fn from_file<P: AsRef<Path>>(p: P) -> Result<Document, Error> {
+        Document::from_file_with(p, &ParserConfig::default())
     }
 
-    pub fn from_file(p: &str) -> Result<Document, String> {
-        let string = match string_from_file(p) {
-            Some(string) => string,
-            None => return Err("Couldn't make String from file".into()),
-        };
-            
-        match Document::from_string(&string) {
-            Ok(document) => Ok(document),
-            Err(e) => Err(e),
-        }
+    pub fn from_file_with<P>(p: P,
+                             config: &ParserConfig) -> Result<Document, Error>
+        where P: AsRef<Path>
+    {
+        Document::from_reader_with(File::open(p)?, config)
+    }
+
+    // parses while reading, without reading
+    // the whole input into memory first
+    pub fn from_reader<R: Read>(r: R) -> Result<Document, Error> {
+        Document::from_reader_with(r, &ParserConfig::default())
+    }
+
+    pub fn from_reader_with<R: Read>(r: R,
+                                     config: &ParserConfig) -> Result<Document, Error> {
+        let chars = CharReader::new(BufReader::new(r));
+        parse_document_with(Tokenizer::new(chars), config)
     }
 
     pub fn get_root(&self) -> &Element {
@@ -58,6 +111,95 @@ impl Document {
         }
     }
 
+    pub fn get_root_mut(&mut self) -> &mut Element {
+        match self.root.get_first_child_mut() {
+            Some(c) => c,
+            None => panic!("Document has no root element!"),
+        }
+    }
+
+    // replaces the root element, keeping the nodes around
+    // it, and returns the old one if there was one
+    pub fn set_root(&mut self, e: Element) -> Option<Element> {
+        let index = self.root.children_nodes()
+            .iter()
+            .position(|n| n.as_element().is_some());
+        match index {
+            Some(i) => {
+                match self.root.replace_child(i, Node::Element(e)) {
+                    Some(Node::Element(old)) => Some(old),
+                    _ => None,
+                }
+            }
+            None => {
+                self.root.append_child(e);
+                None
+            }
+        }
+    }
+
+    // the nodes outside the root element, like processing
+    // instructions, with the root element among them
+    pub fn children_nodes(&self) -> &[Node] {
+        self.root.children_nodes()
+    }
+
+    pub fn append_node(&mut self, n: Node) {
+        self.root.append_node(n);
+    }
+
+    // the <?xml ...?> declaration, if the document has one
+    pub fn declaration(&self) -> Option<&Declaration> {
+        self.declaration.as_ref()
+    }
+
+    pub fn set_declaration(&mut self, declaration: Option<Declaration>) {
+        self.declaration = declaration;
+    }
+
+    // the <!DOCTYPE> declaration, if the document has one
+    pub fn doctype(&self) -> Option<&Doctype> {
+        self.doctype.as_ref()
+    }
+
+    pub fn set_doctype(&mut self, doctype: Option<Doctype>) {
+        self.doctype = doctype;
+    }
+
+    // index in children_nodes of the node the doctype is written
+    // before, it is never written after the root element
+    pub fn doctype_index(&self) -> usize {
+        self.doctype_index
+    }
+
+    pub fn set_doctype_index(&mut self, index: usize) {
+        self.doctype_index = index;
+    }
+
+    // writes the document as XML, including its XML
+    // declaration or the default one
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.write_with(w, &WriterConfig::default())
+    }
+
+    pub fn write_with<W: Write>(&self,
+                                w: &mut W,
+                                config: &WriterConfig) -> io::Result<()> {
+        Writer::new(w, config).write_document(self)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, p: P) -> io::Result<()> {
+        self.save_with(p, &WriterConfig::default())
+    }
+
+    pub fn save_with<P: AsRef<Path>>(&self,
+                                     p: P,
+                                     config: &WriterConfig) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(p)?);
+        self.write_with(&mut w, config)?;
+        w.flush()
+    }
+
     pub fn print(&self) {
         self.root.print(0);
     }
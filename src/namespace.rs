@@ -0,0 +1,214 @@
+use std::fmt;
+
+use super::error::{Error, Position};
+
+// namespaces the xml and xmlns prefixes are bound to,
+// they are never declared in documents
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+pub const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+// a name as written in the document, prefix:local_name,
+// and the namespace its prefix was bound to
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct QName {
+    name: String,
+    // index of the ':' in name
+    colon: Option<usize>,
+    namespace_uri: Option<String>,
+}
+
+impl QName {
+    // a name without a namespace
+    pub fn new(name: &str) -> QName {
+        QName {
+            name: name.to_string(),
+            colon: name.find(':'),
+            namespace_uri: None,
+        }
+    }
+
+    // an empty uri is the same as no namespace
+    pub fn new_ns(uri: &str, name: &str) -> QName {
+        let mut q = QName::new(name);
+        q.set_namespace_uri(Some(uri).filter(|uri| !uri.is_empty()));
+        q
+    }
+
+    // the qualified name, including the prefix
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_prefix(&self) -> Option<&str> {
+        self.colon.map(|i| &self.name[..i])
+    }
+
+    pub fn get_local_name(&self) -> &str {
+        match self.colon {
+            Some(i) => &self.name[i + 1..],
+            None => &self.name,
+        }
+    }
+
+    pub fn get_namespace_uri(&self) -> Option<&str> {
+        self.namespace_uri.as_deref()
+    }
+
+    pub fn set_namespace_uri(&mut self, uri: Option<&str>) {
+        self.namespace_uri = uri.map(|s| s.to_string());
+    }
+
+    // compares the namespace and local name,
+    // whatever prefix was used
+    pub fn matches(&self, uri: Option<&str>, local_name: &str) -> bool {
+        self.get_namespace_uri() == uri && self.get_local_name() == local_name
+    }
+
+    // names can have at most one ':', with
+    // something before and after it
+    pub fn is_valid(&self) -> bool {
+        match self.colon {
+            Some(i) => {
+                i > 0 && i + 1 < self.name.len() && !self.name[i + 1..].contains(':')
+            }
+            None => !self.name.is_empty(),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for QName {
+    fn from(name: &'a str) -> QName {
+        QName::new(name)
+    }
+}
+
+impl PartialEq<str> for QName {
+    fn eq(&self, other: &str) -> bool {
+        self.name == other
+    }
+}
+
+impl fmt::Display for QName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+// the prefixes in scope while reading or writing
+// a document, each element opens a new scope
+#[derive(Clone, Debug, Default)]
+pub struct Namespaces {
+    // prefix and namespace, the default namespace has
+    // an empty prefix and is undeclared with an empty uri
+    bindings: Vec<(String, String)>,
+    // number of bindings when each scope was opened
+    scopes: Vec<usize>,
+}
+
+impl Namespaces {
+    pub fn new() -> Namespaces {
+        Namespaces::default()
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(self.bindings.len());
+    }
+
+    // forgets the bindings declared since
+    // the matching push_scope
+    pub fn pop_scope(&mut self) {
+        if let Some(len) = self.scopes.pop() {
+            self.bindings.truncate(len);
+        }
+    }
+
+    // binds prefix to uri in the current scope, the
+    // reserved prefixes and namespaces can't be rebound
+    pub fn declare(&mut self,
+                   prefix: &str,
+                   uri: &str,
+                   position: Position) -> Result<(), Error> {
+        let valid = match prefix {
+            "xml" => uri == XML_NAMESPACE,
+            "xmlns" => false,
+            "" => uri != XML_NAMESPACE && uri != XMLNS_NAMESPACE,
+            _ => !uri.is_empty() && uri != XML_NAMESPACE && uri != XMLNS_NAMESPACE,
+        };
+        if !valid {
+            return Err(Error::Syntax(
+                format!("Invalid namespace declaration: {}=\"{}\"", prefix, uri),
+                position));
+        }
+        self.bindings.push((prefix.to_string(), uri.to_string()));
+        Ok(())
+    }
+
+    // the namespace prefix is bound to, "" for the default namespace
+    pub fn resolve(&self, prefix: &str) -> Option<&str> {
+        match prefix {
+            "xml" => return Some(XML_NAMESPACE),
+            "xmlns" => return Some(XMLNS_NAMESPACE),
+            _ => {}
+        }
+        self.bindings.iter()
+            .rev()
+            .find(|b| b.0 == prefix)
+            .map(|b| b.1.as_str())
+            .filter(|uri| !uri.is_empty())
+    }
+
+    // whether prefix was declared in the current scope
+    pub fn is_declared(&self, prefix: &str) -> bool {
+        let start = self.scopes.last().cloned().unwrap_or(0);
+        self.bindings[start..].iter().any(|b| b.0 == prefix)
+    }
+
+    // an in-scope prefix bound to uri, the default
+    // namespace is only returned if allow_default is set
+    pub fn prefix_for(&self, uri: &str, allow_default: bool) -> Option<&str> {
+        if uri == XML_NAMESPACE {
+            return Some("xml");
+        }
+        self.bindings.iter()
+            .rev()
+            .map(|b| b.0.as_str())
+            .filter(|p| allow_default || !p.is_empty())
+            .find(|p| self.resolve(p) == Some(uri))
+    }
+
+    // unprefixed element names are in the default namespace
+    pub fn resolve_element(&self,
+                           name: &QName,
+                           position: Position) -> Result<QName, Error> {
+        self.resolve_name(name, name.get_prefix().unwrap_or(""), position)
+    }
+
+    // unprefixed attribute names are in no namespace,
+    // xmlns declarations are in XMLNS_NAMESPACE
+    pub fn resolve_attribute(&self,
+                             name: &QName,
+                             position: Position) -> Result<QName, Error> {
+        if name.get_name() == "xmlns" {
+            return Ok(QName::new_ns(XMLNS_NAMESPACE, "xmlns"));
+        }
+        match name.get_prefix() {
+            Some(prefix) => self.resolve_name(name, prefix, position),
+            None => Ok(QName::new(name.get_name())),
+        }
+    }
+
+    fn resolve_name(&self,
+                    name: &QName,
+                    prefix: &str,
+                    position: Position) -> Result<QName, Error> {
+        if !name.is_valid() {
+            return Err(Error::Syntax(format!("Invalid qualified name: {}", name),
+                                     position));
+        }
+        match self.resolve(prefix) {
+            Some(uri) => Ok(QName::new_ns(uri, name.get_name())),
+            None if prefix.is_empty() => Ok(QName::new(name.get_name())),
+            None => Err(Error::UndeclaredPrefix(prefix.to_string(), position)),
+        }
+    }
+}
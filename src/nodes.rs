@@ -1,13 +1,115 @@
 use std::fmt;
-use std::slice;
-use std::iter::Cloned;
+use std::mem;
 
-#[derive(Clone)]
+use super::namespace::QName;
+use super::traversal::{BreadthFirst, Descendants, Traverse};
+use super::writer::escape_attribute;
+
+// a node inside an element, kept in document order
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+    Comment(String),
+    CData(String),
+    ProcessingInstruction(ProcessingInstruction),
+}
+
+impl Node {
+    pub fn as_element(&self) -> Option<&Element> {
+        match *self {
+            Node::Element(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    pub fn as_element_mut(&mut self) -> Option<&mut Element> {
+        match *self {
+            Node::Element(ref mut e) => Some(e),
+            _ => None,
+        }
+    }
+
+    // returns the character data of text and CDATA nodes
+    pub fn as_text(&self) -> Option<&str> {
+        match *self {
+            Node::Text(ref s) | Node::CData(ref s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Element {
-    name: String,
-    attributes: Vec<Attribute>,
-    children: Vec<Element>,
-    empty: bool,
+    pub(crate) name: QName,
+    pub(crate) attributes: Vec<Attribute>,
+    pub(crate) children: Vec<Node>,
+    pub(crate) empty: bool,
+}
+
+// children are dropped one by one instead of recursively,
+// deeply nested documents would overflow the stack otherwise
+impl Drop for Element {
+    fn drop(&mut self) {
+        let mut nodes = mem::take(&mut self.children);
+        while let Some(n) = nodes.pop() {
+            if let Node::Element(mut e) = n {
+                nodes.append(&mut e.children);
+            }
+        }
+    }
+}
+
+// copied without recursion, like it is dropped
+impl Clone for Element {
+    fn clone(&self) -> Element {
+        // copies of the open elements, with the
+        // children of the originals left to copy
+        let mut stack = vec![(self.clone_without_children(), self.children.iter())];
+        while let Some(&mut (ref mut copy, ref mut children)) = stack.last_mut() {
+            match children.next() {
+                Some(Node::Element(e)) => {
+                    stack.push((e.clone_without_children(), e.children.iter()));
+                }
+                Some(n) => copy.children.push(n.clone()),
+                None => {
+                    let copy = match stack.pop() {
+                        Some((copy, _)) => copy,
+                        None => break,
+                    };
+                    match stack.last_mut() {
+                        Some(&mut (ref mut parent, _)) => parent.children.push(Node::Element(copy)),
+                        None => return copy,
+                    }
+                }
+            }
+        }
+        Element::new_empty()
+    }
+}
+
+// compared without recursion too
+impl PartialEq for Element {
+    fn eq(&self, other: &Element) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            let same = a.name == b.name
+                && a.attributes == b.attributes
+                && a.empty == b.empty
+                && a.children.len() == b.children.len();
+            if !same {
+                return false;
+            }
+            for (m, n) in a.children.iter().zip(&b.children) {
+                match (m, n) {
+                    (Node::Element(x), Node::Element(y)) => stack.push((x, y)),
+                    _ if m != n => return false,
+                    _ => {}
+                }
+            }
+        }
+        true
+    }
 }
 
 impl fmt::Display for Element {
@@ -23,50 +125,117 @@ impl fmt::Display for Element {
 impl Element {
     pub fn new_empty() -> Element {
         Element {
-            name: String::new(),
+            name: QName::new(""),
             attributes: Vec::new(),
             children: Vec::new(),
             empty: false,
         }
     }
     pub fn new(s: &str) -> Element {
+        Element::from_qname(QName::new(s))
+    }
+
+    // an element in the namespace uri, s can have a prefix
+    pub fn new_ns(uri: &str, s: &str) -> Element {
+        Element::from_qname(QName::new_ns(uri, s))
+    }
+
+    pub fn from_qname(q: QName) -> Element {
         Element {
-            name: s.to_string(),
+            name: q,
             attributes: Vec::new(),
             children: Vec::new(),
             empty: false,
         }
     }
 
+    fn clone_without_children(&self) -> Element {
+        Element {
+            name: self.name.clone(),
+            attributes: self.attributes.clone(),
+            children: Vec::with_capacity(self.children.len()),
+            empty: self.empty,
+        }
+    }
+
     pub fn append_child(&mut self, e: Element) {
-        self.children.push(e);
+        self.children.push(Node::Element(e));
+    }
+
+    pub fn append_node(&mut self, n: Node) {
+        self.children.push(n);
     }
-    
+
     pub fn append_attribute(&mut self, a: Attribute) {
         self.attributes.push(a);
     }
 
+    // appends character data, merging it with
+    // a directly preceding text node
+    pub fn append_text(&mut self, t: &str) {
+        if let Some(&mut Node::Text(ref mut s)) = self.children.last_mut() {
+            s.push_str(t);
+            return;
+        }
+        self.children.push(Node::Text(t.to_string()));
+    }
+
+    // replaces all text and CDATA children with t
     pub fn set_text(&mut self, t: &str) {
-        self.attributes.push(Attribute::new("text_data", t));
+        self.children.retain(|n| n.as_text().is_none());
+        self.children.push(Node::Text(t.to_string()));
+    }
+
+    // returns the concatenated character data
+    // of all direct children
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for n in &self.children {
+            if let Some(s) = n.as_text() {
+                text.push_str(s);
+            }
+        }
+        text
     }
 
     pub fn get_name(&self) -> &str {
-        &self.name
+        self.name.get_name()
     }
-    
+
+    // the name without a namespace
     pub fn set_name(&mut self, s: &str) {
-        self.name = s.to_string();
+        self.name = QName::new(s);
+    }
+
+    pub fn get_qname(&self) -> &QName {
+        &self.name
+    }
+
+    pub fn set_qname(&mut self, q: QName) {
+        self.name = q;
+    }
+
+    pub fn get_local_name(&self) -> &str {
+        self.name.get_local_name()
+    }
+
+    pub fn get_prefix(&self) -> Option<&str> {
+        self.name.get_prefix()
+    }
+
+    pub fn get_namespace_uri(&self) -> Option<&str> {
+        self.name.get_namespace_uri()
     }
 
     pub fn get_child_count(&self) -> usize {
-        self.children.len()
+        self.children.iter().filter(|n| n.as_element().is_some()).count()
     }
 
     pub fn set_empty(&mut self, b: bool) {
         self.empty = b;
     }
 
-    pub fn is_empty(&mut self) -> bool {
+    pub fn is_empty(&self) -> bool {
         self.empty
     }
 
@@ -86,62 +255,430 @@ impl Element {
             attr.push_str(&a.print());
         }
 
-        if self.empty == true {
+        if self.empty {
             println!("{}<{}{}/>", s, self.name, attr);
             return;
         }
 
         println!("{}<{}{}>", s, self.name, attr);
-        for e in &self.children {
-            e.print(indent + 1);
+        for n in &self.children {
+            match *n {
+                Node::Element(ref e) => e.print(indent + 1),
+                Node::Text(ref t) => println!("{}\t{}", s, t),
+                Node::Comment(ref c) => println!("{}\t<!--{}-->", s, c),
+                Node::CData(ref c) => println!("{}\t<![CDATA[{}]]>", s, c),
+                Node::ProcessingInstruction(ref pi) => {
+                    println!("{}\t{}", s, pi.print())
+                }
+            }
         }
         println!("{}</{}>", s, self.name);
     }
 
     // methods for accessing element content
     pub fn get_child(&self, name: &str) -> Option<&Element> {
-        self.children.iter().by_ref().find(|ref x| x.get_name() == name)
+        self.children.iter()
+            .filter_map(Node::as_element)
+            .find(|x| x.get_name() == name)
+    }
+
+    // finds a child by namespace and local name,
+    // whatever prefix it was written with
+    pub fn get_child_ns(&self, uri: &str, local_name: &str) -> Option<&Element> {
+        self.children.iter()
+            .filter_map(Node::as_element)
+            .find(|x| x.name.matches(Some(uri), local_name))
     }
 
     pub fn get_first_child(&self) -> Option<&Element> {
-        self.children.first()
+        self.children.iter().filter_map(Node::as_element).next()
+    }
+
+    pub fn get_child_mut(&mut self, name: &str) -> Option<&mut Element> {
+        self.children.iter_mut()
+            .filter_map(Node::as_element_mut)
+            .find(|x| x.get_name() == name)
+    }
+
+    pub fn get_child_ns_mut(&mut self,
+                            uri: &str,
+                            local_name: &str) -> Option<&mut Element> {
+        self.children.iter_mut()
+            .filter_map(Node::as_element_mut)
+            .find(|x| x.name.matches(Some(uri), local_name))
+    }
+
+    pub fn get_first_child_mut(&mut self) -> Option<&mut Element> {
+        self.children.iter_mut().filter_map(Node::as_element_mut).next()
+    }
+
+    // index in children_nodes of the first child element named name
+    pub fn position_of(&self, name: &str) -> Option<usize> {
+        self.children.iter()
+            .position(|n| n.as_element().is_some_and(|e| e.get_name() == name))
+    }
+
+    // inserts n so it ends up at index, panics
+    // if index is past the last node
+    pub fn insert_before(&mut self, index: usize, n: Node) {
+        self.children.insert(index, n);
+    }
+
+    // inserts n right after the node at index,
+    // panics if there is no node at index
+    pub fn insert_after(&mut self, index: usize, n: Node) {
+        assert!(index < self.children.len(),
+                "insert_after: no node at index {}", index);
+        self.children.insert(index + 1, n);
+    }
+
+    // removes the node at index, detaching it from this element
+    pub fn remove_child(&mut self, index: usize) -> Option<Node> {
+        if index >= self.children.len() {
+            return None;
+        }
+        Some(self.children.remove(index))
+    }
+
+    // removes the first child element named name
+    pub fn remove_child_named(&mut self, name: &str) -> Option<Element> {
+        let index = self.position_of(name)?;
+        match self.children.remove(index) {
+            Node::Element(e) => Some(e),
+            _ => None,
+        }
     }
 
-    pub fn iter_children(&self) -> Cloned<slice::Iter<Element>> {
-        // TODO: find a better way to do this
-        self.children.iter().cloned()
+    // puts n in place of the node at index and returns that node
+    pub fn replace_child(&mut self, index: usize, n: Node) -> Option<Node> {
+        let old = self.children.get_mut(index)?;
+        Some(mem::replace(old, n))
+    }
+
+    // keeps only the nodes f returns true for
+    pub fn retain<F: FnMut(&Node) -> bool>(&mut self, f: F) {
+        self.children.retain(f);
+    }
+
+    // removes all child nodes and returns them
+    pub fn take_children(&mut self) -> Vec<Node> {
+        mem::take(&mut self.children)
+    }
+
+    pub fn children_nodes_mut(&mut self) -> &mut [Node] {
+        &mut self.children
+    }
+
+    // the child elements, without the other nodes
+    pub fn children(&self) -> impl Iterator<Item = &Element> + '_ {
+        self.children.iter().filter_map(Node::as_element)
+    }
+
+    pub fn children_mut(&mut self) -> impl Iterator<Item = &mut Element> + '_ {
+        self.children.iter_mut().filter_map(Node::as_element_mut)
+    }
+
+    pub fn children_named<'a>(&'a self,
+                              name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children().filter(move |x| x.get_name() == name)
+    }
+
+    pub fn children_named_mut<'a>(&'a mut self,
+                                  name: &'a str) -> impl Iterator<Item = &'a mut Element> + 'a {
+        self.children_mut().filter(move |x| x.get_name() == name)
+    }
+
+    // all elements below this one, depth-first in document order
+    pub fn descendants(&self) -> Descendants<'_> {
+        Descendants::new(self)
+    }
+
+    // this element and its descendants as Start and End
+    // edges, in the order their tags appear in the document
+    pub fn traverse(&self) -> Traverse<'_> {
+        Traverse::new(self)
+    }
+
+    // all elements below this one, the children first,
+    // then the grandchildren and so on
+    pub fn breadth_first(&self) -> BreadthFirst<'_> {
+        BreadthFirst::new(self)
+    }
+
+    #[deprecated(note = "clones every child, use children() instead")]
+    pub fn iter_children(&self) -> impl Iterator<Item = Element> + '_ {
+        self.children().cloned()
+    }
+
+    // all child nodes, including text, comments,
+    // CDATA sections and processing instructions
+    pub fn children_nodes(&self) -> &[Node] {
+        &self.children
     }
 
     pub fn get_attribute(&self, name: &str) -> Option<&Attribute> {
-        self.attributes.iter().by_ref().find(|ref x| x.get_name() == name)
+        self.attributes.iter().by_ref().find(|x| x.get_name() == name)
     }
 
-    pub fn iter_attributes(&self) -> Cloned<slice::Iter<Attribute>> {
-        // TODO: find a better way to do this
-        self.attributes.iter().cloned()
+    pub fn get_attribute_mut(&mut self, name: &str) -> Option<&mut Attribute> {
+        self.attributes.iter_mut().find(|x| x.get_name() == name)
+    }
+
+    // changes the value of the attribute named name,
+    // or appends it if there is none
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        match self.get_attribute_mut(name) {
+            Some(a) => a.set_value(value),
+            None => self.attributes.push(Attribute::new(name, value)),
+        }
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) -> Option<Attribute> {
+        let index = self.attributes.iter().position(|x| x.get_name() == name)?;
+        Some(self.attributes.remove(index))
+    }
+
+    pub fn get_attribute_ns(&self, uri: &str, local_name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|x| x.name.matches(Some(uri), local_name))
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = &Attribute> + '_ {
+        self.attributes.iter()
+    }
+
+    pub fn attributes_mut(&mut self) -> impl Iterator<Item = &mut Attribute> + '_ {
+        self.attributes.iter_mut()
+    }
+
+    #[deprecated(note = "clones every attribute, use attributes() instead")]
+    pub fn iter_attributes(&self) -> impl Iterator<Item = Attribute> + '_ {
+        self.attributes().cloned()
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Attribute {
-    name: String,
+    name: QName,
     value: String,
 }
 
 impl Attribute {
     pub fn new(n: &str,
                v: &str) -> Attribute {
+        Attribute::from_qname(QName::new(n), v)
+    }
+
+    pub fn new_ns(uri: &str,
+                  n: &str,
+                  v: &str) -> Attribute {
+        Attribute::from_qname(QName::new_ns(uri, n), v)
+    }
+
+    pub fn from_qname(q: QName,
+                      v: &str) -> Attribute {
         Attribute {
-            name: n.to_string(),
+            name: q,
             value: v.to_string(),
         }
     }
 
     pub fn get_name(&self) -> &str {
+        self.name.get_name()
+    }
+
+    pub fn get_qname(&self) -> &QName {
         &self.name
     }
 
+    pub fn set_qname(&mut self, q: QName) {
+        self.name = q;
+    }
+
+    pub fn get_local_name(&self) -> &str {
+        self.name.get_local_name()
+    }
+
+    pub fn get_namespace_uri(&self) -> Option<&str> {
+        self.name.get_namespace_uri()
+    }
+
+    pub fn get_value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn set_value(&mut self, v: &str) {
+        self.value = v.to_string();
+    }
+
     pub fn print(&self) -> String {
-        format!("{}=\"{}\"", self.name, self.value)
+        format!("{}=\"{}\"", self.name, escape_attribute(&self.value))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessingInstruction {
+    target: String,
+    data: String,
+}
+
+impl ProcessingInstruction {
+    pub fn new(t: &str,
+               d: &str) -> ProcessingInstruction {
+        ProcessingInstruction {
+            target: t.to_string(),
+            data: d.to_string(),
+        }
+    }
+
+    pub fn get_target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn get_data(&self) -> &str {
+        &self.data
+    }
+
+    pub fn set_data(&mut self, d: &str) {
+        self.data = d.to_string();
+    }
+
+    pub fn print(&self) -> String {
+        if self.data.is_empty() {
+            return format!("<?{}?>", self.target);
+        }
+        format!("<?{} {}?>", self.target, self.data)
+    }
+}
+
+// document type declaration:
+// <!DOCTYPE name PUBLIC "public_id" "system_id" [internal_subset]>
+#[derive(Clone, Debug, PartialEq)]
+pub struct Doctype {
+    name: String,
+    public_id: Option<String>,
+    system_id: Option<String>,
+    internal_subset: Option<String>,
+}
+
+impl Doctype {
+    pub fn new(n: &str) -> Doctype {
+        Doctype {
+            name: n.to_string(),
+            public_id: None,
+            system_id: None,
+            internal_subset: None,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_public_id(&self) -> Option<&str> {
+        self.public_id.as_deref()
+    }
+
+    pub fn set_public_id(&mut self, s: Option<&str>) {
+        self.public_id = s.map(|s| s.to_string());
+    }
+
+    pub fn get_system_id(&self) -> Option<&str> {
+        self.system_id.as_deref()
+    }
+
+    pub fn set_system_id(&mut self, s: Option<&str>) {
+        self.system_id = s.map(|s| s.to_string());
+    }
+
+    // the declarations between '[' and ']', as written
+    pub fn get_internal_subset(&self) -> Option<&str> {
+        self.internal_subset.as_deref()
+    }
+
+    pub fn set_internal_subset(&mut self, s: Option<&str>) {
+        self.internal_subset = s.map(|s| s.to_string());
+    }
+
+    pub fn print(&self) -> String {
+        let mut s = format!("<!DOCTYPE {}", self.name);
+        match (&self.public_id, &self.system_id) {
+            (Some(p), Some(id)) => {
+                s.push_str(&format!(" PUBLIC {} {}", quote(p), quote(id)));
+            }
+            (None, Some(id)) => {
+                s.push_str(&format!(" SYSTEM {}", quote(id)));
+            }
+            _ => {}
+        }
+        if let Some(ref subset) = self.internal_subset {
+            s.push_str(&format!(" [{}]", subset));
+        }
+        s.push('>');
+        s
+    }
+}
+
+// literals can't be escaped, so the quote is
+// chosen by what the literal contains
+fn quote(s: &str) -> String {
+    if s.contains('"') {
+        return format!("'{}'", s);
+    }
+    format!("\"{}\"", s)
+}
+
+// XML declaration:
+// <?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+#[derive(Clone, Debug, PartialEq)]
+pub struct Declaration {
+    version: String,
+    encoding: Option<String>,
+    standalone: Option<bool>,
+}
+
+impl Declaration {
+    pub fn new(version: &str) -> Declaration {
+        Declaration {
+            version: version.to_string(),
+            encoding: None,
+            standalone: None,
+        }
+    }
+
+    pub fn get_version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn set_version(&mut self, v: &str) {
+        self.version = v.to_string();
+    }
+
+    pub fn get_encoding(&self) -> Option<&str> {
+        self.encoding.as_deref()
+    }
+
+    pub fn set_encoding(&mut self, e: Option<&str>) {
+        self.encoding = e.map(|e| e.to_string());
+    }
+
+    pub fn get_standalone(&self) -> Option<bool> {
+        self.standalone
+    }
+
+    pub fn set_standalone(&mut self, s: Option<bool>) {
+        self.standalone = s;
+    }
+
+    pub fn print(&self) -> String {
+        let mut s = format!("<?xml version=\"{}\"", self.version);
+        if let Some(ref e) = self.encoding {
+            s.push_str(&format!(" encoding=\"{}\"", e));
+        }
+        if let Some(standalone) = self.standalone {
+            s.push_str(&format!(" standalone=\"{}\"",
+                                if standalone { "yes" } else { "no" }));
+        }
+        s.push_str("?>");
+        s
     }
 }
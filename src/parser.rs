@@ -1,5 +1,48 @@
+use std::mem;
+
+use super::error::{Error, Position};
 use super::tokenizer::Token;
+use super::reader::Reader;
+use super::sax::{self, ContentHandler};
+use super::namespace::QName;
 use super::nodes::*;
+use super::Document;
+
+// options controlling how documents are parsed
+#[derive(Clone, Debug)]
+pub struct ParserConfig {
+    // drop text nodes that only contain whitespace,
+    // unless they are inside xml:space="preserve"
+    pub trim_whitespace: bool,
+    // resolve namespace prefixes, names with
+    // undeclared prefixes are errors
+    pub namespace_aware: bool,
+    // reject everything that isn't well-formed XML 1.0,
+    // otherwise some mistakes are tolerated, like
+    // elements left open at the end of the document
+    pub strict: bool,
+    // drop comments instead of keeping them as nodes
+    pub ignore_comments: bool,
+    // how deep entity references may be nested
+    pub max_entity_depth: usize,
+    // how many bytes of text entities may expand to in
+    // total, in the whole document, every reference
+    // counts as many bytes as it takes in the source
+    pub max_entity_expansion: usize,
+}
+
+impl Default for ParserConfig {
+    fn default() -> ParserConfig {
+        ParserConfig {
+            trim_whitespace: false,
+            namespace_aware: true,
+            strict: false,
+            ignore_comments: false,
+            max_entity_depth: 16,
+            max_entity_expansion: 10 * 1024 * 1024,
+        }
+    }
+}
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ParseState {
@@ -11,211 +54,133 @@ pub enum ParseState {
     InMD,
 }
 
-pub fn parse(tokens: Vec<Token>) -> Result<Element, String> {
-    let mut state_stack = Vec::<ParseState>::new();
-    let mut elements = Vec::<Element>::new();
-
-    let mut current_state = ParseState::InDocument;
-    let mut current_element = Element::new("root");
-
-    for token in tokens {
-        match token {
-            Token::Left => {
-                match current_state {
-                    ParseState::InDocument => {
-                        // Found beginning of tag, 
-                        // create a new element for it
-                        // and push the old one
-                        elements.push(current_element.clone());
-                        current_element = Element::new_empty();
-
-                        // push old state to stack, set new state
-                        state_stack.push(current_state);
-                        current_state = ParseState::InStartTag;
-                    }
-                    ParseState::InElement => {
-                        // Found nested Element,
-                        // create a new element for it
-                        // and push the old one
-                        elements.push(current_element.clone());
-                        current_element = Element::new_empty();
-
-                        // since we will still be in an element
-                        // after this one ends, push the state
-                        // and set new current
-                        state_stack.push(current_state.clone());
-                        current_state = ParseState::InStartTag;
-                    }
-                    _ => return token_not_allowed(
-                        &token,
-                        &current_state),
-                }
-            }
-            Token::ClosingLeft => {
-                match current_state {
-                    ParseState::InElement => {
-                        // Closing tag found
-                        // we are not in element anymore, 
-                        // so just set current state
-                        current_state = ParseState::InEndTag;
-                    }
-                    _ => return token_not_allowed(
-                            &token, 
-                            &current_state),
-                }
-            }
-            Token::Right => {
-                match current_state {
-                    ParseState::InStartTag => {
-                        // tag ends, body starts
-                        current_state = ParseState::InElement;
-                    }
-                    ParseState::InEndTag => {
-                        // tag ends, element is completed
-                        // see if there is a parent
-                        // if there is no parent, we have reached
-                        // the end of the file, return the current element
-
-                        let mut parent = match elements.pop() {
-                            Some(parent) => parent,
-                            None => return Ok(current_element),
-                        };
-                        // otherwise, append current element as parent
-                        // and make parent the new current
-                        parent.append_child(current_element.clone());
-                        current_element = parent;
-
-                        // also, go 1 state up
-                        current_state = match state_stack.pop() {
-                            Some(state) => state,
-                            // since InDocument state should always
-                            // be there, there's an error
-                            None => return Err("Unexpected closing tag: >".into()),
-                        }
-                    }
-                    ParseState::InMD => {
-                        // MD end found
-                        current_state = match state_stack.pop() {
-                            Some(state) => state,
-                            None => return Err("Unexpected closing tag: >".into()),
-                        }
-                    }
-                    _ => return token_not_allowed(
-                            &token, 
-                            &current_state),
-                }
-            }
-            Token::ClosingRight => {
-                match current_state {
-                    ParseState::InStartTag => {
-                        // Empty element found,
-                        // set empty push it
-                        current_element.set_empty(true);
-
-                        // check if there's a parent
-                        let mut parent = match elements.pop() {
-                            Some(parent) => parent,
-                            // If not, we're returning the root element
-                            None => return Ok(current_element),
-                        };
-                        // otherwise, append current element as parent
-                        // and make parent the new current
-                        parent.append_child(current_element.clone());
-                        current_element = parent;
-
-                        // state is now the top state from the stack
-                        current_state = match state_stack.pop() {
-                            Some(state) => state,
-                            // since InDocument state should always
-                            // be there, there's an error
-                            None => return Err("Unexpected closing tag: />".into()),
-                        }
-                    }
-                    _ => return token_not_allowed(
-                            &token, 
-                            &current_state),
-                }
-            }
-            Token::Value(ref n, ref v) => {
-                match current_state {
-                    // attributes are only allowed in
-                    // start tags
-                    ParseState::InStartTag => {
-                        current_element.append_attribute(
-                            (Attribute::new(&n, &v)));
-                    }
-                    ParseState::InMD | ParseState::InPI => {
-                        // Ignore MD and PI for now
-                        continue;
-                    }
-                    _ => return token_not_allowed(
-                            &token, 
-                            &current_state),
-                }
-            }
-            Token::Text(ref s) => {
-                match current_state {
-                    ParseState::InStartTag => {
-                        // tag name found
-                        current_element.set_name(s);
-                    }
-                    ParseState::InEndTag => {
-                        // tag name found
-                        // check if tags match
-                        if current_element.get_name() != s {
-                            return Err(format!("Expected closing tag: {}, found closing tag: {}",
-                                               current_element.get_name(),
-                                               s));
-                        }
-                    }
-                    ParseState::InElement => {
-                        // found text data attribute
-                        // inside element
-                        current_element.set_text(s);
-                    }
-                    ParseState::InMD | ParseState::InPI => {
-                        // Ignore MD and PI for now
-                        continue;
-                    }
-                    _ => return token_not_allowed(
-                            &token, 
-                            &current_state),
-                }
-            }
-            Token::PILeft => {
-                state_stack.push(current_state.clone());
-                current_state = ParseState::InPI;
-            }
-            Token::PIRight => {
-                match current_state {
-                    ParseState::InPI => {
-                        // state is now the top state from the stack
-                        current_state = match state_stack.pop() {
-                            Some(state) => state,
-                            // since InDocument state should always
-                            // be there, there's an error
-                            None => return Err("Unexpected closing tag: ?>".into()),
-                        }
-                    }
-                    _ => return token_not_allowed(
-                            &token, 
-                            &current_state),
-                }
-            }
-            Token::MDLeft => {
-                // found markup declaration, ignore it for now
-                state_stack.push(current_state.clone());
-                current_state = ParseState::InMD;
-            }
-            Token::Comment => {
-                // Ignore comments
-            }
+pub fn parse<I>(tokens: I) -> Result<Element, Error>
+    where I: IntoIterator<Item = Result<(Token, Position), Error>>
+{
+    parse_with(tokens, &ParserConfig::default())
+}
+
+pub fn parse_with<I>(tokens: I,
+                     config: &ParserConfig) -> Result<Element, Error>
+    where I: IntoIterator<Item = Result<(Token, Position), Error>>
+{
+    let mut reader = Reader::with_config(tokens.into_iter(), config.clone());
+    let mut builder = DomBuilder::new();
+    sax::parse(&mut reader, &mut builder)?;
+    Ok(builder.into_element())
+}
+
+// parses a whole document, keeping what comes
+// before the root element
+pub fn parse_document_with<I>(tokens: I,
+                              config: &ParserConfig) -> Result<Document, Error>
+    where I: IntoIterator<Item = Result<(Token, Position), Error>>
+{
+    let mut reader = Reader::with_config(tokens.into_iter(), config.clone());
+    let mut builder = DomBuilder::new();
+    sax::parse(&mut reader, &mut builder)?;
+    Ok(builder.into_document())
+}
+
+// content handler that builds the element tree,
+// the resulting element contains the top level nodes
+pub struct DomBuilder {
+    // open elements, the innermost one is current
+    elements: Vec<Element>,
+    current_element: Element,
+    declaration: Option<Declaration>,
+    doctype: Option<Doctype>,
+    doctype_index: usize,
+}
+
+impl Default for DomBuilder {
+    fn default() -> DomBuilder {
+        DomBuilder::new()
+    }
+}
+
+impl DomBuilder {
+    pub fn new() -> DomBuilder {
+        DomBuilder {
+            elements: Vec::new(),
+            current_element: Element::new("root"),
+            declaration: None,
+            doctype: None,
+            doctype_index: 0,
+        }
+    }
+
+    // closes elements that are still open
+    // and returns the top level element
+    pub fn into_element(mut self) -> Element {
+        while !self.elements.is_empty() {
+            self.close_element();
+        }
+        self.current_element
+    }
+
+    pub fn into_document(mut self) -> Document {
+        let declaration = self.declaration.take();
+        let doctype = self.doctype.take();
+        let doctype_index = self.doctype_index;
+        let mut document = Document::from_element(self.into_element());
+        document.set_declaration(declaration);
+        document.set_doctype(doctype);
+        document.set_doctype_index(doctype_index);
+        document
+    }
+
+    // appends the current element to its parent
+    // and makes that current
+    fn close_element(&mut self) {
+        if let Some(parent) = self.elements.pop() {
+            let element = mem::replace(&mut self.current_element, parent);
+            self.current_element.append_child(element);
         }
     }
-    Ok(current_element)
 }
 
-pub fn token_not_allowed(t: &Token,
-                         p: &ParseState) -> Result<Element, String> {
-    Err(format!("Token not allowed: {:?}, state: {:?}", t, p))
+impl ContentHandler for DomBuilder {
+    fn start_element(&mut self,
+                     name: &QName,
+                     attributes: &[Attribute],
+                     empty: bool) {
+        // create a new element
+        // and push the old one
+        let mut element = Element::from_qname(name.clone());
+        element.set_attributes(attributes.to_vec());
+        element.set_empty(empty);
+        self.elements.push(mem::replace(&mut self.current_element, element));
+    }
+
+    fn end_element(&mut self, _name: &QName) {
+        self.close_element();
+    }
+
+    fn characters(&mut self, text: &str) {
+        self.current_element.append_text(text);
+    }
+
+    fn cdata(&mut self, text: &str) {
+        self.current_element.append_node(Node::CData(text.into()));
+    }
+
+    fn comment(&mut self, text: &str) {
+        self.current_element.append_node(Node::Comment(text.into()));
+    }
+
+    fn processing_instruction(&mut self, target: &str, data: &str) {
+        let pi = ProcessingInstruction::new(target, data);
+        self.current_element.append_node(Node::ProcessingInstruction(pi));
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) {
+        self.declaration = Some(declaration.clone());
+    }
+
+    fn doctype(&mut self, doctype: &Doctype) {
+        // it can only appear at the top level
+        self.doctype = Some(doctype.clone());
+        self.doctype_index = self.current_element.children_nodes().len();
+    }
 }
@@ -0,0 +1,789 @@
+use std::collections::VecDeque;
+use std::io::{BufReader, Read};
+use std::mem;
+
+use super::dtd::parse_internal_subset;
+use super::entities::{is_xml_char, Entities, Expander};
+use super::error::{Error, Position};
+use super::namespace::{Namespaces, QName};
+use super::tokenizer::*;
+use super::parser::{ParseState, ParserConfig};
+use super::nodes::*;
+
+// events produced by the pull parser,
+// in document order
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    // empty elements are followed by
+    // their EndElement right away
+    StartElement {
+        name: QName,
+        attributes: Vec<Attribute>,
+        empty: bool,
+    },
+    EndElement {
+        name: QName,
+    },
+    Text(String),
+    Comment(String),
+    // always the first event if the document has one
+    Declaration(Declaration),
+    ProcessingInstruction(ProcessingInstruction),
+    Doctype(Doctype),
+    CData(String),
+    Eof,
+}
+
+// pull parser, reads one event at a time without
+// building a tree, only the names of open elements
+// are kept in memory
+pub struct Reader<T>
+    where T: Iterator<Item = Result<(Token, Position), Error>>
+{
+    tokens: T,
+    config: ParserConfig,
+
+    state_stack: Vec<ParseState>,
+    current_state: ParseState,
+
+    // names of the open elements
+    open: Vec<QName>,
+    // prefixes declared by the open elements
+    namespaces: Namespaces,
+    // whether whitespace is preserved in the current element,
+    // with the values of its ancestors on the stack
+    preserve_stack: Vec<bool>,
+    preserve: bool,
+
+    // start tag, PI or markup declaration being read
+    name: String,
+    attributes: Vec<Attribute>,
+    markup: Vec<Token>,
+    pi_position: Position,
+    // whether the PI being read started the document
+    pi_first: bool,
+    // whether the end tag being read has a name
+    end_named: bool,
+    subset_position: Position,
+
+    // entities declared in the internal subset, and how
+    // many bytes they were expanded to so far
+    entities: Entities,
+    expanded: usize,
+
+    // whether the root element was opened, and closed
+    root_started: bool,
+    root_ended: bool,
+    doctype_seen: bool,
+    // whether any token was read yet
+    started: bool,
+    // whether the last token was whitespace inside a tag
+    space: bool,
+
+    pending: VecDeque<Event>,
+    position: Position,
+    finished: bool,
+}
+
+impl<'a> Reader<Tokenizer<StrChars<'a>>> {
+    pub fn from_string(s: &'a str) -> Reader<Tokenizer<StrChars<'a>>> {
+        Reader::new(Tokenizer::new(str_chars(s)))
+    }
+}
+
+impl<R: Read> Reader<Tokenizer<CharReader<BufReader<R>>>> {
+    pub fn from_reader(r: R) -> Reader<Tokenizer<CharReader<BufReader<R>>>> {
+        Reader::new(Tokenizer::new(CharReader::new(BufReader::new(r))))
+    }
+}
+
+impl<T> Reader<T>
+    where T: Iterator<Item = Result<(Token, Position), Error>>
+{
+    pub fn new(tokens: T) -> Reader<T> {
+        Reader::with_config(tokens, ParserConfig::default())
+    }
+
+    pub fn with_config(tokens: T, config: ParserConfig) -> Reader<T> {
+        Reader {
+            tokens,
+            config,
+            state_stack: Vec::new(),
+            current_state: ParseState::InDocument,
+            open: Vec::new(),
+            namespaces: Namespaces::new(),
+            preserve_stack: Vec::new(),
+            preserve: false,
+            name: String::new(),
+            attributes: Vec::new(),
+            markup: Vec::new(),
+            pi_position: Position::new(),
+            pi_first: false,
+            end_named: false,
+            subset_position: Position::new(),
+            entities: Entities::new(),
+            expanded: 0,
+            root_started: false,
+            root_ended: false,
+            doctype_seen: false,
+            started: false,
+            space: false,
+            pending: VecDeque::new(),
+            position: Position::new(),
+            finished: false,
+        }
+    }
+
+    // position of the token the last event was read from
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    // depth of the current element, 0 outside the root
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    // returns the next event, Eof is returned at the end
+    // and on every call after it or after an error
+    pub fn next_event(&mut self) -> Result<Event, Error> {
+        match self.read_event() {
+            Ok(event) => Ok(event),
+            Err(e) => {
+                // the state after an error doesn't match
+                // the document, nothing more is read
+                self.finished = true;
+                self.pending.clear();
+                Err(e)
+            }
+        }
+    }
+
+    fn read_event(&mut self) -> Result<Event, Error> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+            if self.finished {
+                return Ok(Event::Eof);
+            }
+
+            let (token, position) = match self.tokens.next() {
+                Some(token) => token?,
+                None => {
+                    if self.config.strict {
+                        self.check_end()?;
+                    }
+                    self.finished = true;
+                    continue;
+                }
+            };
+            self.position = position;
+            self.read_token(token)?;
+            self.started = true;
+        }
+    }
+
+    // feeds a token to the state machine,
+    // queueing any events it completes
+    fn read_token(&mut self, token: Token) -> Result<(), Error> {
+        if let Token::Space = token {
+            self.space = true;
+            return Ok(());
+        }
+        let space = mem::replace(&mut self.space, false);
+        if self.config.strict {
+            self.check_space(&token, space)?;
+        }
+
+        match token {
+            Token::Left => {
+                match self.current_state {
+                    ParseState::InDocument if self.config.strict && self.root_ended => {
+                        return Err(Error::Syntax("More than one root element".into(),
+                                                 self.position));
+                    }
+                    ParseState::InDocument | ParseState::InElement => {
+                        // Found beginning of tag, since we will
+                        // still be in the same state after it ends,
+                        // push the state and set new current
+                        self.state_stack.push(self.current_state);
+                        self.current_state = ParseState::InStartTag;
+                    }
+                    _ => return self.token_not_allowed(&token),
+                }
+            }
+            Token::ClosingLeft => {
+                match self.current_state {
+                    ParseState::InElement => {
+                        // Closing tag found
+                        // we are not in element anymore,
+                        // so just set current state
+                        self.current_state = ParseState::InEndTag;
+                        self.end_named = false;
+                    }
+                    _ => return self.token_not_allowed(&token),
+                }
+            }
+            Token::Right => {
+                match self.current_state {
+                    ParseState::InStartTag => {
+                        // tag ends, body starts
+                        self.current_state = ParseState::InElement;
+                        self.start_element(false)?;
+                    }
+                    ParseState::InEndTag => {
+                        if self.config.strict && !self.end_named {
+                            return Err(Error::Syntax("End tag without name".into(),
+                                                     self.position));
+                        }
+                        // tag ends, element is completed
+                        self.preserve = self.preserve_stack.pop()
+                            .unwrap_or(false);
+                        self.end_element();
+                        self.pop_state()?;
+                    }
+                    ParseState::InMD => {
+                        // MD end found
+                        let markup = mem::take(&mut self.markup);
+                        let doctype = self.read_doctype(&markup)?;
+                        self.pending.push_back(Event::Doctype(doctype));
+                        self.pop_state()?;
+                    }
+                    _ => return self.token_not_allowed(&token),
+                }
+            }
+            Token::ClosingRight => {
+                match self.current_state {
+                    ParseState::InStartTag => {
+                        // Empty element found, it ends
+                        // right away
+                        self.start_element(true)?;
+                        self.end_element();
+                        self.pop_state()?;
+                    }
+                    _ => return self.token_not_allowed(&token),
+                }
+            }
+            Token::Value(ref n, ref v) => {
+                match self.current_state {
+                    // attributes are only allowed in
+                    // start tags
+                    ParseState::InStartTag if !self.name.is_empty() => {
+                        if self.config.strict {
+                            if let Some(i) = v.find('<') {
+                                return Err(Error::InvalidCharacter(
+                                    '<', advanced(self.position, &v[..i])));
+                            }
+                            self.check_chars(v, self.position)?;
+                        }
+                        let value = Expander::new(&self.entities,
+                                                  &self.config,
+                                                  &mut self.expanded)
+                            .attribute(v, self.position)?;
+                        self.attributes.push(Attribute::new(n, &value));
+                    }
+                    ParseState::InMD | ParseState::InPI => {
+                        if self.config.strict {
+                            self.check_chars(v, self.position)?;
+                        }
+                        self.markup.push(token.clone());
+                    }
+                    _ => return self.token_not_allowed(&token),
+                }
+            }
+            Token::Text(ref s) => {
+                match self.current_state {
+                    ParseState::InStartTag => {
+                        // tag name found
+                        if !self.name.is_empty() {
+                            return Err(Error::Syntax(
+                                format!("Attribute without value: {}", s),
+                                self.position));
+                        }
+                        self.name = s.clone();
+                    }
+                    ParseState::InEndTag => {
+                        // tag name found
+                        // check if tags match
+                        let expected = self.open.last()
+                            .map(|q| q.get_name().to_string())
+                            .unwrap_or_default();
+                        if &expected != s {
+                            return Err(Error::MismatchedTag {
+                                expected,
+                                found: s.clone(),
+                                position: self.position,
+                            });
+                        }
+                        self.end_named = true;
+                    }
+                    ParseState::InElement => {
+                        // found text inside element
+                        if self.config.strict {
+                            self.check_text(s)?;
+                        }
+                        let blank = s.chars().all(is_whitespace);
+                        if blank && self.config.trim_whitespace && !self.preserve {
+                            return Ok(());
+                        }
+                        let text = Expander::new(&self.entities,
+                                                 &self.config,
+                                                 &mut self.expanded)
+                            .text(s, self.position)?;
+                        self.pending.push_back(Event::Text(text));
+                    }
+                    ParseState::InDocument => {
+                        // whitespace between top level nodes
+                        // is not part of the document
+                        if !s.chars().all(is_whitespace) {
+                            return self.token_not_allowed(&token);
+                        }
+                    }
+                    ParseState::InMD | ParseState::InPI => {
+                        if self.config.strict {
+                            self.check_chars(s, self.position)?;
+                        }
+                        self.markup.push(token.clone());
+                    }
+                }
+            }
+            Token::PILeft => {
+                self.pi_position = self.position;
+                self.pi_first = !self.started;
+                self.state_stack.push(self.current_state);
+                self.current_state = ParseState::InPI;
+            }
+            Token::PIRight => {
+                match self.current_state {
+                    ParseState::InPI => {
+                        let markup = mem::take(&mut self.markup);
+                        let target = match markup.first() {
+                            Some(Token::Text(t)) => t.clone(),
+                            _ => {
+                                return Err(Error::Syntax(
+                                    "Processing instruction without target".into(),
+                                    self.position));
+                            }
+                        };
+                        if self.config.strict && target != "xml" && is_reserved(&target) {
+                            return Err(Error::Syntax(
+                                format!("Reserved processing instruction target: {}", target),
+                                self.pi_position));
+                        }
+                        if target == "xml" {
+                            let declaration = self.read_declaration(&markup[1..])?;
+                            self.pending.push_back(Event::Declaration(declaration));
+                        }
+                        else {
+                            let data = match markup.get(1) {
+                                Some(Token::Text(data)) => data.as_str(),
+                                _ => "",
+                            };
+                            self.pending.push_back(Event::ProcessingInstruction(
+                                ProcessingInstruction::new(&target, data)));
+                        }
+                        self.pop_state()?;
+                    }
+                    _ => return self.token_not_allowed(&token),
+                }
+            }
+            Token::MDLeft => {
+                self.state_stack.push(self.current_state);
+                self.current_state = ParseState::InMD;
+            }
+            Token::Comment(ref s) => {
+                match self.current_state {
+                    ParseState::InDocument | ParseState::InElement => {
+                        if self.config.strict {
+                            let mut position = self.position;
+                            for c in "<!--".chars() {
+                                position.advance(c, 1);
+                            }
+                            self.check_chars(s, position)?;
+                        }
+                        if !self.config.ignore_comments {
+                            self.pending.push_back(Event::Comment(s.clone()));
+                        }
+                    }
+                    _ => return self.token_not_allowed(&token),
+                }
+            }
+            Token::CData(ref s) => {
+                match self.current_state {
+                    ParseState::InElement => {
+                        if self.config.strict {
+                            let mut position = self.position;
+                            for c in "<![CDATA[".chars() {
+                                position.advance(c, 1);
+                            }
+                            self.check_chars(s, position)?;
+                        }
+                        self.pending.push_back(Event::CData(s.clone()));
+                    }
+                    _ => return self.token_not_allowed(&token),
+                }
+            }
+            Token::Subset(ref s) => {
+                match self.current_state {
+                    ParseState::InMD => {
+                        self.subset_position = self.position;
+                        if self.config.strict {
+                            self.check_chars(s, advanced(self.position, "["))?;
+                        }
+                        self.markup.push(token.clone());
+                    }
+                    _ => return self.token_not_allowed(&token),
+                }
+            }
+            Token::Space => {}
+        }
+        Ok(())
+    }
+
+    // names have to follow '<', '</' and '<?' directly, attributes,
+    // pseudo-attributes and the data of PIs need whitespace before them
+    fn check_space(&self, token: &Token, space: bool) -> Result<(), Error> {
+        let message = match (self.current_state, token) {
+            (ParseState::InStartTag, &Token::Text(_)) if space && self.name.is_empty() => {
+                "Whitespace after '<'"
+            }
+            (ParseState::InEndTag, &Token::Text(_)) if space => "Whitespace after '</'",
+            (ParseState::InPI, &Token::Text(_)) if space && self.markup.is_empty() => {
+                "Whitespace after '<?'"
+            }
+            (ParseState::InStartTag, &Token::Value(..))
+            | (ParseState::InPI, &Token::Value(..)) if !space => "Missing whitespace",
+            (ParseState::InPI, &Token::Text(_)) if !space && !self.markup.is_empty() => {
+                "Missing whitespace"
+            }
+            _ => return Ok(()),
+        };
+        Err(Error::Syntax(message.into(), self.position))
+    }
+
+    // reads the pseudo-attributes of an XML declaration,
+    // which must come first and in this order:
+    // version="1.x" [encoding="name"] [standalone="yes|no"]
+    fn read_declaration(&self, markup: &[Token]) -> Result<Declaration, Error> {
+        // only a byte order mark may come before it,
+        // which the tokenizer doesn't return
+        if !self.pi_first {
+            return Err(Error::Syntax(
+                "XML declaration not at the start of the document".into(),
+                self.pi_position));
+        }
+
+        let mut tokens = markup.iter().peekable();
+        let mut declaration = match tokens.next() {
+            Some(Token::Value(n, v)) if n == "version" && is_version(v) => {
+                Declaration::new(v)
+            }
+            _ => return Err(self.invalid_declaration()),
+        };
+        if let Some(Token::Value(n, v)) = tokens.peek() {
+            if n == "encoding" {
+                if !is_encoding_name(v) {
+                    return Err(self.invalid_declaration());
+                }
+                declaration.set_encoding(Some(v));
+                tokens.next();
+            }
+        }
+        if let Some(Token::Value(n, v)) = tokens.peek() {
+            if n == "standalone" {
+                let standalone = match v.as_str() {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(self.invalid_declaration()),
+                };
+                declaration.set_standalone(Some(standalone));
+                tokens.next();
+            }
+        }
+
+        if tokens.next().is_some() {
+            return Err(self.invalid_declaration());
+        }
+        Ok(declaration)
+    }
+
+    fn invalid_declaration(&self) -> Error {
+        Error::Syntax("Invalid XML declaration".into(), self.pi_position)
+    }
+
+    // reads a document type declaration and the
+    // entity declarations of its internal subset:
+    // DOCTYPE name [SYSTEM "uri" | PUBLIC "id" "uri"] [[subset]]
+    fn read_doctype(&mut self, markup: &[Token]) -> Result<Doctype, Error> {
+        if self.config.strict {
+            // only one, before the root element
+            let misplaced = self.doctype_seen
+                || self.root_started
+                || self.state_stack.last() != Some(&ParseState::InDocument);
+            if misplaced {
+                return Err(Error::Syntax("Misplaced document type declaration".into(),
+                                         self.position));
+            }
+        }
+        self.doctype_seen = true;
+
+        let mut tokens = markup.iter();
+        match tokens.next() {
+            Some(Token::Text(s)) if s == "DOCTYPE" => {}
+            _ => {
+                return Err(Error::Syntax(
+                    format!("Unknown markup declaration: {}", join_markup(markup)),
+                    self.position));
+            }
+        }
+
+        let mut doctype = match tokens.next() {
+            Some(Token::Text(name)) => Doctype::new(name),
+            _ => return Err(self.invalid_doctype()),
+        };
+
+        let mut next = tokens.next();
+        match next {
+            Some(Token::Text(s)) if s == "SYSTEM" => {
+                match tokens.next() {
+                    Some(Token::Value(n, id)) if n.is_empty() => {
+                        doctype.set_system_id(Some(id));
+                    }
+                    _ => return Err(self.invalid_doctype()),
+                }
+                next = tokens.next();
+            }
+            Some(Token::Text(s)) if s == "PUBLIC" => {
+                match (tokens.next(), tokens.next()) {
+                    (Some(Token::Value(n, p)), Some(Token::Value(m, id)))
+                        if n.is_empty() && m.is_empty() => {
+                        doctype.set_public_id(Some(p));
+                        doctype.set_system_id(Some(id));
+                    }
+                    _ => return Err(self.invalid_doctype()),
+                }
+                next = tokens.next();
+            }
+            _ => {}
+        }
+
+        if let Some(Token::Subset(s)) = next {
+            // the subset starts after its '['
+            let mut position = self.subset_position;
+            position.advance('[', 1);
+            self.entities = parse_internal_subset(s, position, &self.config)?;
+            doctype.set_internal_subset(Some(s));
+            next = tokens.next();
+        }
+
+        if next.is_some() {
+            return Err(self.invalid_doctype());
+        }
+        Ok(doctype)
+    }
+
+    fn invalid_doctype(&self) -> Error {
+        Error::Syntax("Invalid document type declaration".into(),
+                      self.position)
+    }
+
+    // queues the start tag that was just read
+    fn start_element(&mut self, empty: bool) -> Result<(), Error> {
+        if self.name.is_empty() {
+            return Err(Error::Syntax("Tag without name".into(),
+                                     self.position));
+        }
+
+        // xml:space is inherited by the content
+        if !empty {
+            self.preserve_stack.push(self.preserve);
+            for a in &self.attributes {
+                if a.get_name() == "xml:space" {
+                    self.preserve = a.get_value() == "preserve";
+                }
+            }
+        }
+
+        self.root_started = true;
+        let mut name = QName::new(&mem::take(&mut self.name));
+        let mut attributes = mem::take(&mut self.attributes);
+        if self.config.namespace_aware {
+            self.resolve_names(&mut name, &mut attributes)?;
+        }
+        if self.config.strict {
+            self.check_attributes(&attributes)?;
+        }
+
+        self.open.push(name.clone());
+        self.pending.push_back(Event::StartElement {
+            name,
+            attributes,
+            empty,
+        });
+        Ok(())
+    }
+
+    // declares the namespaces of a start tag in a new
+    // scope and binds the prefixes of its names
+    fn resolve_names(&mut self,
+                     name: &mut QName,
+                     attributes: &mut [Attribute]) -> Result<(), Error> {
+        self.namespaces.push_scope();
+        for a in attributes.iter() {
+            if a.get_name() == "xmlns" {
+                self.namespaces.declare("", a.get_value(), self.position)?;
+            }
+            else if let Some(prefix) = a.get_name().strip_prefix("xmlns:") {
+                self.namespaces.declare(prefix, a.get_value(), self.position)?;
+            }
+        }
+
+        *name = self.namespaces.resolve_element(name, self.position)?;
+        for a in attributes.iter_mut() {
+            let q = self.namespaces.resolve_attribute(a.get_qname(), self.position)?;
+            a.set_qname(q);
+        }
+        Ok(())
+    }
+
+    // checks the rest of a start tag, once its names are known
+    fn check_attributes(&self, attributes: &[Attribute]) -> Result<(), Error> {
+        for (i, a) in attributes.iter().enumerate() {
+            let duplicate = attributes[..i].iter().any(|b| {
+                b.get_name() == a.get_name()
+                    || (a.get_namespace_uri().is_some()
+                        && b.get_qname().matches(a.get_namespace_uri(),
+                                                 a.get_local_name()))
+            });
+            if duplicate {
+                return Err(Error::Syntax(format!("Duplicate attribute: {}", a.get_name()),
+                                         self.position));
+            }
+        }
+        Ok(())
+    }
+
+    // character data can't contain "]]>"
+    fn check_text(&self, s: &str) -> Result<(), Error> {
+        self.check_chars(s, self.position)?;
+        if let Some(i) = s.find("]]>") {
+            return Err(Error::Syntax("']]>' not allowed in text".into(),
+                                     advanced(self.position, &s[..i])));
+        }
+        Ok(())
+    }
+
+    // finds characters that aren't allowed in XML
+    // documents, s starts at position in the source
+    fn check_chars(&self, s: &str, position: Position) -> Result<(), Error> {
+        let mut position = position;
+        for c in s.chars() {
+            if !is_xml_char(c) {
+                return Err(Error::InvalidCharacter(c, position));
+            }
+            position.advance(c, c.len_utf8());
+        }
+        Ok(())
+    }
+
+    // at the end of input, the root element
+    // has to be there and closed
+    fn check_end(&self) -> Result<(), Error> {
+        if let Some(name) = self.open.last() {
+            return Err(Error::Syntax(format!("Element not closed: {}", name),
+                                     self.position));
+        }
+        if self.current_state != ParseState::InDocument {
+            return Err(Error::UnexpectedEof(self.position));
+        }
+        if !self.root_started {
+            return Err(Error::Syntax("No root element".into(), self.position));
+        }
+        Ok(())
+    }
+
+    // queues the end of the innermost open element
+    fn end_element(&mut self) {
+        let name = self.open.pop().unwrap_or_default();
+        if self.open.is_empty() {
+            self.root_ended = true;
+        }
+        if self.config.namespace_aware {
+            self.namespaces.pop_scope();
+        }
+        self.pending.push_back(Event::EndElement {
+            name,
+        });
+    }
+
+    fn pop_state(&mut self) -> Result<(), Error> {
+        self.current_state = match self.state_stack.pop() {
+            Some(state) => state,
+            // since InDocument state should always
+            // be there, there's an error
+            None => return Err(Error::Syntax("Unexpected closing tag".into(),
+                                             self.position)),
+        };
+        Ok(())
+    }
+
+    fn token_not_allowed(&self, t: &Token) -> Result<(), Error> {
+        Err(Error::Syntax(format!("Token not allowed: {:?}, state: {:?}",
+                                  t,
+                                  self.current_state),
+                          self.position))
+    }
+}
+
+// the position after s, if it starts at position
+fn advanced(position: Position, s: &str) -> Position {
+    let mut position = position;
+    for c in s.chars() {
+        position.advance(c, c.len_utf8());
+    }
+    position
+}
+
+// PI targets starting with "xml" in any case are reserved,
+// only the ones that were standardized can be used
+fn is_reserved(target: &str) -> bool {
+    let reserved = target.get(..3).is_some_and(|s| s.eq_ignore_ascii_case("xml"));
+    reserved && target != "xml-stylesheet" && target != "xml-model"
+}
+
+// 1.0, 1.1 and so on
+fn is_version(s: &str) -> bool {
+    match s.strip_prefix("1.") {
+        Some(minor) => !minor.is_empty() && minor.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+// [A-Za-z] ([A-Za-z0-9._] | '-')*
+fn is_encoding_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+}
+
+// writes the tokens of a markup declaration
+// back as text, separated by spaces
+fn join_markup(tokens: &[Token]) -> String {
+    let mut parts = Vec::new();
+    for t in tokens {
+        match *t {
+            Token::Text(ref s) => parts.push(s.clone()),
+            Token::Value(ref n, ref v) if n.is_empty() => {
+                parts.push(format!("\"{}\"", v))
+            }
+            Token::Value(ref n, ref v) => parts.push(format!("{}=\"{}\"", n, v)),
+            Token::Subset(ref s) => parts.push(format!("[{}]", s)),
+            _ => {}
+        }
+    }
+    parts.join(" ")
+}
@@ -0,0 +1,79 @@
+use super::error::{Error, Position};
+use super::namespace::QName;
+use super::tokenizer::Token;
+use super::reader::{Event, Reader};
+use super::nodes::*;
+
+// callbacks for push parsing, every method
+// does nothing unless it is implemented
+pub trait ContentHandler {
+    fn start_document(&mut self) {}
+
+    fn end_document(&mut self) {}
+
+    // empty is true for elements written as <a/>,
+    // end_element follows right after them
+    fn start_element(&mut self,
+                     _name: &QName,
+                     _attributes: &[Attribute],
+                     _empty: bool) {}
+
+    fn end_element(&mut self, _name: &QName) {}
+
+    fn characters(&mut self, _text: &str) {}
+
+    // content of a CDATA section, passed
+    // on as characters by default
+    fn cdata(&mut self, text: &str) {
+        self.characters(text);
+    }
+
+    fn declaration(&mut self, _declaration: &Declaration) {}
+
+    fn comment(&mut self, _text: &str) {}
+
+    fn processing_instruction(&mut self, _target: &str, _data: &str) {}
+
+    fn doctype(&mut self, _doctype: &Doctype) {}
+
+    // called before parsing stops because of e
+    fn error(&mut self, _e: &Error) {}
+}
+
+// reads all events from the reader and
+// passes them to the handler
+pub fn parse<T, H>(reader: &mut Reader<T>,
+                   handler: &mut H) -> Result<(), Error>
+    where T: Iterator<Item = Result<(Token, Position), Error>>,
+          H: ContentHandler + ?Sized
+{
+    handler.start_document();
+    loop {
+        let event = match reader.next_event() {
+            Ok(event) => event,
+            Err(e) => {
+                handler.error(&e);
+                return Err(e);
+            }
+        };
+
+        match event {
+            Event::StartElement { ref name, ref attributes, empty } => {
+                handler.start_element(name, attributes, empty);
+            }
+            Event::EndElement { ref name } => handler.end_element(name),
+            Event::Text(ref s) => handler.characters(s),
+            Event::CData(ref s) => handler.cdata(s),
+            Event::Declaration(ref d) => handler.declaration(d),
+            Event::Comment(ref s) => handler.comment(s),
+            Event::ProcessingInstruction(ref pi) => {
+                handler.processing_instruction(pi.get_target(), pi.get_data());
+            }
+            Event::Doctype(ref d) => handler.doctype(d),
+            Event::Eof => {
+                handler.end_document();
+                return Ok(());
+            }
+        }
+    }
+}
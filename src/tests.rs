@@ -16,8 +16,8 @@ fn tokenize_string() {
     use super::tokenizer::*;
 
     let string = match string_from_file("res/test.xml") {
-        Some(string) => string,
-        None => panic!("failed to load string from file"),
+        Ok(string) => string,
+        Err(e) => panic!("failed to load string from file: {}", e),
     };
 
     match tokenize(&string) {
@@ -27,6 +27,7 @@ fn tokenize_string() {
 }
 
 #[test]
+#[allow(deprecated)]
 fn iter_children() {
     use super::Document;
     let xml_doc_test 
@@ -59,3 +60,1204 @@ fn get_child() {
 
     println!("child found: {}", child.get_name());
 }
+
+#[test]
+fn write_round_trip() {
+    use super::Document;
+    let xml_doc_test
+        = match Document::from_file("res/player_light.sprite") {
+        Ok(xml_doc_test) => xml_doc_test,
+        Err(e) => panic!("loading failed: {}", e),
+    };
+
+    let written = xml_doc_test.to_string();
+    assert!(written.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+    assert!(written.contains("<dimensions w=\"32\" h=\"38\"/>"));
+
+    let reparsed = match Document::from_string(&written) {
+        Ok(reparsed) => reparsed,
+        Err(e) => panic!("reparsing failed: {}", e),
+    };
+    assert_eq!(reparsed.to_string(), written);
+}
+
+#[test]
+fn write_escaped() {
+    use super::Document;
+    use super::nodes::*;
+
+    let mut root = Element::new("root");
+    let mut sprite = Element::new("sprite");
+    sprite.append_attribute(Attribute::new("name", "a \"b\" & <c>"));
+    sprite.set_empty(true);
+    root.append_child(sprite);
+
+    let written = Document::from_element(root).to_string();
+    assert!(written.contains(
+        "<sprite name=\"a &quot;b&quot; &amp; &lt;c>\"/>"));
+}
+
+#[test]
+fn write_with_config() {
+    use super::Document;
+    use super::nodes::*;
+    use super::writer::*;
+
+    let mut root = Element::new("frames");
+    let mut frame = Element::new("frame");
+    frame.append_attribute(Attribute::new("name", "player-0-0"));
+    frame.append_attribute(Attribute::new("duration", "0.3"));
+    root.append_child(frame);
+    let mut wrapper = Element::new("root");
+    wrapper.append_child(root);
+    let doc = Document::from_element(wrapper);
+
+    let config = WriterConfig {
+        indent: "  ".into(),
+        newline: Newline::CrLf,
+        attributes_per_line: Some(1),
+        sort_attributes: true,
+        collapse_empty: true,
+        ..WriterConfig::default()
+    };
+    let mut written = Vec::new();
+    doc.write_with(&mut written, &config).unwrap();
+
+    assert_eq!(String::from_utf8(written).unwrap(),
+               "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n\
+                <frames>\r\n  \
+                <frame\r\n    \
+                duration=\"0.3\"\r\n    \
+                name=\"player-0-0\"/>\r\n\
+                </frames>\r\n");
+}
+
+#[test]
+fn mixed_content() {
+    use super::Document;
+    use super::nodes::*;
+
+    let doc = match Document::from_string("<a>x<b/>y</a>") {
+        Ok(doc) => doc,
+        Err(e) => panic!("parsing failed: {}", e),
+    };
+    let root = doc.get_root();
+
+    assert_eq!(root.text(), "xy");
+    match root.children_nodes() {
+        [Node::Text(ref x), Node::Element(ref b), Node::Text(ref y)] => {
+            assert_eq!(x, "x");
+            assert_eq!(b.get_name(), "b");
+            assert_eq!(y, "y");
+        }
+        _ => panic!("unexpected children"),
+    }
+    assert!(doc.to_string().contains("<a>x<b/>y</a>"));
+
+    let mut e = Element::new("a");
+    e.append_attribute(Attribute::new("text_data", "1"));
+    e.set_text("x");
+    assert_eq!(e.text(), "x");
+    assert_eq!(e.get_attribute("text_data").unwrap().get_value(), "1");
+}
+
+#[test]
+fn preserve_whitespace() {
+    use super::Document;
+    use super::parser::ParserConfig;
+    use super::writer::WriterConfig;
+
+    let xml = "<a>\n\t<b>hal  lo\n\tx</b>\n\t<c xml:space=\"preserve\"> <d> </d> </c>\n</a>";
+    let doc = match Document::from_string(xml) {
+        Ok(doc) => doc,
+        Err(e) => panic!("parsing failed: {}", e),
+    };
+    let root = doc.get_root();
+    assert_eq!(root.get_child("b").unwrap().text(), "hal  lo\n\tx");
+    assert_eq!(root.children_nodes().len(), 5);
+
+    let config = ParserConfig {
+        trim_whitespace: true,
+        ..ParserConfig::default()
+    };
+    let doc = match Document::from_string_with(xml, &config) {
+        Ok(doc) => doc,
+        Err(e) => panic!("parsing failed: {}", e),
+    };
+    let root = doc.get_root();
+    assert_eq!(root.children_nodes().len(), 2);
+    assert_eq!(root.get_child("b").unwrap().text(), "hal  lo\n\tx");
+    let c = root.get_child("c").unwrap();
+    assert_eq!(c.children_nodes().len(), 3);
+    assert_eq!(c.get_child("d").unwrap().text(), " ");
+
+    // xml:space is inherited when writing too
+    let writer_config = WriterConfig {
+        preserve_text: false,
+        write_declaration: false,
+        ..WriterConfig::default()
+    };
+    let xml = "<r><c xml:space=\"preserve\"><d>  x  </d></c><e>  y  </e></r>";
+    let doc = Document::from_string(xml).unwrap();
+    let mut buf = Vec::new();
+    doc.write_with(&mut buf, &writer_config).unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(),
+               "<r>\n\t<c xml:space=\"preserve\"><d>  x  </d></c>\n\t<e>\n\t\ty\n\t</e>\n</r>\n");
+}
+
+#[test]
+fn byte_order_mark() {
+    use super::Document;
+
+    // a leading byte order mark is skipped, anywhere else it is text
+    let doc = Document::from_string("\u{feff}<a>\u{feff}</a>").unwrap();
+    assert_eq!(doc.get_root().text(), "\u{feff}");
+    let doc = Document::from_reader("\u{feff}<a/>".as_bytes()).unwrap();
+    assert_eq!(doc.get_root().get_name(), "a");
+    assert!(Document::from_string("\u{feff}\u{feff}<a/>").is_err());
+}
+
+#[test]
+fn error_position() {
+    use super::{Document, Error};
+
+    let xml = "<a>\n\t<b>text</c>\n</a>";
+    let e = match Document::from_string(xml) {
+        Ok(_) => panic!("mismatched tag was accepted"),
+        Err(e) => e,
+    };
+    match e {
+        Error::MismatchedTag { ref expected, ref found, position } => {
+            assert_eq!(expected, "b");
+            assert_eq!(found, "c");
+            assert_eq!(position.line, 2);
+            assert_eq!(position.column, 11);
+            assert_eq!(position.offset, 14);
+        }
+        _ => panic!("unexpected error: {}", e),
+    }
+    assert_eq!(e.render_source(xml).unwrap(),
+               "2 | \t<b>text</c>\n  | \t         ^");
+
+    match Document::from_string("<a b=\"1\"") {
+        Err(Error::UnexpectedEof(position)) => assert_eq!(position.offset, 8),
+        _ => panic!("expected end of file error"),
+    }
+}
+
+#[test]
+fn file_not_found() {
+    use std::io;
+    use super::{Document, Error};
+
+    match Document::from_file("res/missing.xml") {
+        Err(Error::Io(ref e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        _ => panic!("expected an I/O error"),
+    }
+}
+
+#[test]
+fn parse_from_reader() {
+    use std::io::{self, Read};
+    use super::{Document, Error};
+
+    // hands out a single byte per read,
+    // splitting multi byte characters
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    let xml = "<sprite name=\"spieler\">\r\n\t<frame>größe €</frame>\r\n</sprite>";
+    let doc = match Document::from_reader(Trickle(xml.as_bytes())) {
+        Ok(doc) => doc,
+        Err(e) => panic!("parsing failed: {}", e),
+    };
+    let frame = doc.get_root().get_child("frame").unwrap();
+    assert_eq!(frame.text(), "größe €");
+    assert_eq!(doc.get_root().text(), "\n\t\n");
+
+    match Document::from_reader(Trickle(b"<a>\xff</a>")) {
+        Err(Error::Io(ref e)) => {
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData)
+        }
+        _ => panic!("expected an I/O error"),
+    }
+}
+
+#[test]
+fn pull_events() {
+    use super::reader::*;
+    use super::nodes::*;
+
+    let xml = "<?editor hint?><!DOCTYPE sprite SYSTEM \"sprite.dtd\">\
+               <sprite name=\"player\"><frame w=\"32\"/>text</sprite>";
+    let mut reader = Reader::from_string(xml);
+    let mut doctype = Doctype::new("sprite");
+    doctype.set_system_id(Some("sprite.dtd"));
+    let mut events = Vec::new();
+    loop {
+        match reader.next_event() {
+            Ok(Event::Eof) => break,
+            Ok(event) => events.push(event),
+            Err(e) => panic!("reading failed: {}", e),
+        }
+    }
+
+    assert_eq!(events, vec![
+        Event::ProcessingInstruction(ProcessingInstruction::new("editor", "hint")),
+        Event::Doctype(doctype),
+        Event::StartElement {
+            name: "sprite".into(),
+            attributes: vec![Attribute::new("name", "player")],
+            empty: false,
+        },
+        Event::StartElement {
+            name: "frame".into(),
+            attributes: vec![Attribute::new("w", "32")],
+            empty: true,
+        },
+        Event::EndElement { name: "frame".into() },
+        Event::Text("text".into()),
+        Event::EndElement { name: "sprite".into() },
+    ]);
+    assert_eq!(reader.next_event().unwrap(), Event::Eof);
+
+    // nothing is read after an error
+    let mut reader = Reader::from_string("<a></b><c/>");
+    assert!(reader.next_event().is_ok());
+    assert!(reader.next_event().is_err());
+    assert_eq!(reader.next_event().unwrap(), Event::Eof);
+    assert_eq!(reader.next_event().unwrap(), Event::Eof);
+}
+
+#[test]
+fn sax_handler() {
+    use super::Error;
+    use super::nodes::*;
+    use super::namespace::QName;
+    use super::reader::Reader;
+    use super::sax::{self, ContentHandler};
+
+    #[derive(Default)]
+    struct FrameCounter {
+        frames: Vec<String>,
+        characters: String,
+        errors: usize,
+        ended: bool,
+    }
+
+    impl ContentHandler for FrameCounter {
+        fn start_element(&mut self,
+                         name: &QName,
+                         attributes: &[Attribute],
+                         _empty: bool) {
+            if name == "frame" {
+                let a = attributes.iter().find(|a| a.get_name() == "name");
+                self.frames.push(a.unwrap().get_value().into());
+            }
+        }
+
+        fn characters(&mut self, text: &str) {
+            self.characters.push_str(text.trim());
+        }
+
+        fn error(&mut self, _e: &Error) {
+            self.errors += 1;
+        }
+
+        fn end_document(&mut self) {
+            self.ended = true;
+        }
+    }
+
+    let file = ::std::fs::File::open("res/player_light.sprite").unwrap();
+    let mut counter = FrameCounter::default();
+    sax::parse(&mut Reader::from_reader(file), &mut counter).unwrap();
+    assert_eq!(counter.frames, vec!["player-0-0.png", "player-0-0",
+                                    "player-0-1", "player-0-2", "player-0-3"]);
+    assert_eq!(counter.characters, "hal lo");
+    assert!(counter.ended);
+
+    let mut counter = FrameCounter::default();
+    let result = sax::parse(&mut Reader::from_string("<a></b>"), &mut counter);
+    assert!(result.is_err());
+    assert_eq!(counter.errors, 1);
+    assert!(!counter.ended);
+}
+
+#[test]
+fn cdata_section() {
+    use super::Document;
+    use super::nodes::*;
+
+    let script = "if (a < b && c) {\n\tprint(\"<b>\");\n}";
+    let xml = format!("<script>\n<![CDATA[{}]]></script>", script);
+    let doc = match Document::from_string(&xml) {
+        Ok(doc) => doc,
+        Err(e) => panic!("parsing failed: {}", e),
+    };
+    let root = doc.get_root();
+    assert_eq!(root.children_nodes()[1], Node::CData(script.into()));
+    assert_eq!(root.text(), format!("\n{}", script));
+    assert!(doc.to_string().contains(&xml));
+
+    let mut e = Element::new("a");
+    e.append_node(Node::CData("x]]>y".into()));
+    let mut root = Element::new("root");
+    root.append_child(e);
+    let written = Document::from_element(root).to_string();
+    assert!(written.contains("<a><![CDATA[x]]]]><![CDATA[>y]]></a>"));
+    let reparsed = Document::from_string(&written).unwrap();
+    assert_eq!(reparsed.get_root().text(), "x]]>y");
+}
+
+#[test]
+fn character_references() {
+    use super::{Document, Error};
+
+    let xml = "<a b=\"&quot;x&quot; &amp; &#65;&#x42;\">&lt;tag&gt; &apos;&#x20AC;&apos;</a>";
+    let doc = match Document::from_string(xml) {
+        Ok(doc) => doc,
+        Err(e) => panic!("parsing failed: {}", e),
+    };
+    let root = doc.get_root();
+    assert_eq!(root.get_attribute("b").unwrap().get_value(), "\"x\" & AB");
+    assert_eq!(root.text(), "<tag> '€'");
+    assert!(doc.to_string().contains(
+        "<a b=\"&quot;x&quot; &amp; AB\">&lt;tag&gt; '€'</a>"));
+
+    for bad in &["<a>&foo;</a>", "<a>&#0;</a>", "<a>&#x110000;</a>",
+                 "<a>&#xZ;</a>", "<a b=\"&amp\"/>", "<a>&#+1;</a>"] {
+        match Document::from_string(bad) {
+            Err(Error::InvalidReference(..)) => {}
+            _ => panic!("accepted invalid reference in {}", bad),
+        }
+    }
+
+    match Document::from_string("<a>\n  x &bad; y</a>") {
+        Err(Error::InvalidReference(_, position)) => {
+            assert_eq!(position.line, 2);
+            assert_eq!(position.column, 5);
+        }
+        _ => panic!("expected an invalid reference"),
+    }
+
+    // references in attribute values are located inside the value,
+    // whose whitespace is only normalized after locating them
+    for &(bad, line, column) in &[("<a bb=\"xx&bad;\"/>", 1, 10),
+                                  ("<a bb = 'x\n  y &bad;'/>", 2, 5)] {
+        match Document::from_string(bad) {
+            Err(Error::InvalidReference(_, position)) => {
+                assert_eq!((position.line, position.column), (line, column));
+            }
+            _ => panic!("expected an invalid reference in {}", bad),
+        }
+    }
+    let doc = Document::from_string("<a b='x\n\ty&#10;'/>").unwrap();
+    assert_eq!(doc.get_root().get_attribute("b").unwrap().get_value(), "x  y\n");
+
+    // carriage returns survive the normalization of line endings
+    let doc = Document::from_string("<a>x&#13;\ny</a>").unwrap();
+    assert_eq!(doc.get_root().text(), "x\r\ny");
+    let written = doc.to_string();
+    assert!(written.contains("<a>x&#13;\ny</a>"));
+    assert_eq!(Document::from_string(&written).unwrap().get_root().text(), "x\r\ny");
+}
+
+#[test]
+fn dtd_entities() {
+    use super::{Document, Error};
+    use super::parser::ParserConfig;
+
+    let xml = "<!DOCTYPE config [\n\
+               \t<!ENTITY % common '<!ENTITY vendor \"n0va\">'>\n\
+               \t%common;\n\
+               \t<!ELEMENT config ANY>\n\
+               \t<!ENTITY version \"1.2\">\n\
+               \t<!ENTITY full \"&vendor; &version;&#33;\">\n\
+               \t<!ENTITY version \"ignored\">\n\
+               ]>\n\
+               <config v=\"&version;\">&full;</config>";
+    let doc = match Document::from_string(xml) {
+        Ok(doc) => doc,
+        Err(e) => panic!("parsing failed: {}", e),
+    };
+    assert_eq!(doc.get_root().get_attribute("v").unwrap().get_value(), "1.2");
+    assert_eq!(doc.get_root().text(), "n0va 1.2!");
+
+    let laughs = "<!DOCTYPE lolz [\n\
+                  <!ENTITY lol \"lol\">\n\
+                  <!ENTITY lol1 \"&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;\">\n\
+                  <!ENTITY lol2 \"&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;\">\n\
+                  <!ENTITY lol3 \"&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;\">\n\
+                  <!ENTITY lol4 \"&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;\">\n\
+                  ]>\n\
+                  <lolz>&lol4;</lolz>";
+    assert!(Document::from_string(laughs).is_ok());
+
+    let config = ParserConfig {
+        max_entity_expansion: 10000,
+        ..ParserConfig::default()
+    };
+    match Document::from_string_with(laughs, &config) {
+        Err(Error::LimitExceeded(..)) => {}
+        _ => panic!("expansion limit was not enforced"),
+    }
+    // entities that expand to nothing are limited by their references
+    let mut empty = String::from("<!DOCTYPE a [<!ENTITY e0 \"\">");
+    for i in 1..8 {
+        empty.push_str(&format!("<!ENTITY e{} \"{}\">",
+                                i,
+                                format!("&e{};", i - 1).repeat(30)));
+    }
+    empty.push_str("]><a>&e7;</a>");
+    match Document::from_string(&empty) {
+        Err(Error::LimitExceeded(..)) => {}
+        _ => panic!("expansion limit was not enforced for empty entities"),
+    }
+
+    let config = ParserConfig {
+        max_entity_depth: 3,
+        ..ParserConfig::default()
+    };
+    match Document::from_string_with(laughs, &config) {
+        Err(Error::LimitExceeded(..)) => {}
+        _ => panic!("depth limit was not enforced"),
+    }
+
+    for bad in &["<!DOCTYPE a [<!ENTITY a \"&b;\"><!ENTITY b \"&a;\">]><a>&a;</a>",
+                 "<!DOCTYPE a [<!ENTITY e SYSTEM \"file:///etc/passwd\">]><a>&e;</a>",
+                 "<!DOCTYPE a [<!ENTITY e \"<b>\">]><a b=\"&e;\"/>"] {
+        assert!(Document::from_string(bad).is_err(), "accepted {}", bad);
+    }
+
+    // markup in replacement text isn't parsed, so it is refused
+    // instead of ending up in the document as text
+    for markup in &["<!DOCTYPE a [<!ENTITY e \"<b/>\">]><a>&e;</a>",
+                    "<!DOCTYPE a [<!ENTITY e \"&#60;b/>\">]><a>&e;</a>"] {
+        match Document::from_string(markup) {
+            Err(Error::InvalidReference(..)) => {}
+            _ => panic!("accepted markup in replacement text: {}", markup),
+        }
+    }
+    let doc = Document::from_string("<!DOCTYPE a [<!ENTITY e \"&#38;#60;b/>\">]><a>&e;</a>")
+        .unwrap();
+    assert_eq!(doc.get_root().text(), "<b/>");
+
+    // quotes and ']' in comments and PIs of the subset are skipped
+    for xml in &["<!DOCTYPE a [<?pi it's?>]><a/>",
+                 "<!DOCTYPE a [<?pi ]?><!ENTITY e \"x\">]><a>&e;</a>",
+                 "<!DOCTYPE a [<!-- it's ] --><!ENTITY e \"x\">]><a>&e;</a>"] {
+        let d = match Document::from_string(xml) {
+            Ok(d) => d,
+            Err(e) => panic!("parsing {:?} failed: {}", xml, e),
+        };
+        assert!(d.doctype().unwrap().get_internal_subset().unwrap().ends_with('>'));
+    }
+}
+
+#[test]
+fn doctype() {
+    use super::*;
+
+    let xml = "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \
+               \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\">\
+               <html/>";
+    let d = Document::from_string(xml).unwrap();
+    let doctype = d.doctype().unwrap();
+    assert_eq!(doctype.get_name(), "html");
+    assert_eq!(doctype.get_public_id(), Some("-//W3C//DTD XHTML 1.0 Strict//EN"));
+    assert_eq!(doctype.get_system_id(),
+               Some("http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd"));
+    assert_eq!(doctype.get_internal_subset(), None);
+
+    let xml = "<!DOCTYPE note SYSTEM \"note.dtd\" [<!ENTITY who \"me\">]><note>&who;</note>";
+    let d = Document::from_string(xml).unwrap();
+    assert_eq!(d.doctype().unwrap().get_internal_subset(),
+               Some("<!ENTITY who \"me\">"));
+    assert_eq!(d.get_root().text(), "me");
+
+    // the declaration is written back unchanged
+    let written = d.to_string();
+    assert!(written.contains("<!DOCTYPE note SYSTEM \"note.dtd\" [<!ENTITY who \"me\">]>"));
+    let again = Document::from_string(&written).unwrap();
+    assert_eq!(again.doctype(), d.doctype());
+
+    assert!(Document::from_string("<!DOCTYPE a SYSTEM><a/>").is_err());
+    assert!(Document::from_string("<!ELEMENT a ANY><a/>").is_err());
+
+    // nodes before the declaration stay before it
+    let xml = "<!-- c --><!DOCTYPE a><?p?><a/>";
+    let d = Document::from_string(xml).unwrap();
+    assert_eq!(d.doctype_index(), 1);
+    let config = WriterConfig {
+        write_declaration: false,
+        ..WriterConfig::default()
+    };
+    let mut buf = Vec::new();
+    d.write_with(&mut buf, &config).unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(),
+               "<!-- c -->\n<!DOCTYPE a>\n<?p?>\n<a/>\n");
+    let arena = arena::ArenaDocument::from_string(xml).unwrap();
+    assert_eq!(arena.to_document().doctype_index(), 1);
+
+    // but it is never written after the root element
+    let mut d = Document::from_string("<a/>").unwrap();
+    d.set_doctype(Some(Doctype::new("a")));
+    d.set_doctype_index(5);
+    let mut buf = Vec::new();
+    d.write_with(&mut buf, &config).unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(), "<!DOCTYPE a>\n<a/>\n");
+}
+
+#[test]
+fn xml_declaration() {
+    use super::*;
+
+    let d = Document::from_file("res/test.xml").unwrap();
+    let declaration = d.declaration().unwrap();
+    assert_eq!(declaration.get_version(), "1.0");
+    assert_eq!(declaration.get_encoding(), Some("UTF-8"));
+    assert_eq!(declaration.get_standalone(), Some(false));
+    assert!(d.to_string()
+        .starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n"));
+
+    let d = Document::from_string("<?xml version='1.1'?><a/>").unwrap();
+    assert_eq!(d.declaration().unwrap().get_encoding(), None);
+    assert_eq!(d.to_string(), "<?xml version=\"1.1\"?>\n<a/>\n");
+
+    let mut declaration = Declaration::new("1.0");
+    declaration.set_standalone(Some(true));
+    let config = WriterConfig {
+        declaration: Some(declaration),
+        ..WriterConfig::default()
+    };
+    let mut written = Vec::new();
+    d.write_with(&mut written, &config).unwrap();
+    assert_eq!(String::from_utf8(written).unwrap(),
+               "<?xml version=\"1.0\" standalone=\"yes\"?>\n<a/>\n");
+
+    let config = WriterConfig {
+        write_declaration: false,
+        ..WriterConfig::default()
+    };
+    let mut written = Vec::new();
+    d.write_with(&mut written, &config).unwrap();
+    assert_eq!(String::from_utf8(written).unwrap(), "<a/>\n");
+
+    for bad in &[" <?xml version=\"1.0\"?><a/>",
+                 "<a><?xml version=\"1.0\"?></a>",
+                 "<?xml encoding=\"UTF-8\"?><a/>",
+                 "<?xml version=\"1.0\" standalone=\"maybe\"?><a/>",
+                 "<?xml version=\"1.0\" standalone=\"no\" encoding=\"UTF-8\"?><a/>"] {
+        assert!(Document::from_string(bad).is_err(), "accepted {}", bad);
+    }
+
+    // a byte order mark can come before the declaration
+    let d = Document::from_string("\u{feff}<?xml version=\"1.0\"?><a/>").unwrap();
+    assert_eq!(d.declaration().unwrap().get_version(), "1.0");
+
+    // the writer only writes UTF-8, whatever the document was read from
+    let d = Document::from_string("<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a/>")
+        .unwrap();
+    assert_eq!(d.declaration().unwrap().get_encoding(), Some("ISO-8859-1"));
+    assert_eq!(d.to_string(), "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<a/>\n");
+}
+
+#[test]
+fn processing_instructions() {
+    use super::*;
+
+    let xml = "<?xml-stylesheet href=\"a.xsl\" type=\"text/xsl\"?>\
+               <sprite><?editor hint?><frame/></sprite>\
+               <?editor done?>";
+    let d = Document::from_string(xml).unwrap();
+
+    let pis: Vec<&ProcessingInstruction> = d.children_nodes()
+        .iter()
+        .filter_map(|n| match *n {
+            Node::ProcessingInstruction(ref pi) => Some(pi),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(pis.len(), 2);
+    assert_eq!(pis[0].get_target(), "xml-stylesheet");
+    assert_eq!(pis[0].get_data(), "href=\"a.xsl\" type=\"text/xsl\"");
+    assert_eq!(pis[1].get_data(), "done");
+
+    let root = d.get_root();
+    assert_eq!(root.children_nodes()[0],
+               Node::ProcessingInstruction(ProcessingInstruction::new("editor", "hint")));
+    assert_eq!(root.get_child_count(), 1);
+
+    assert_eq!(d.to_string(),
+               "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                <?xml-stylesheet href=\"a.xsl\" type=\"text/xsl\"?>\n\
+                <sprite>\n\
+                \t<?editor hint?>\n\
+                \t<frame/>\n\
+                </sprite>\n\
+                <?editor done?>\n");
+
+    // the data is kept as it is written, whatever it looks like
+    for &data in &["path/to", "x > y", "a=b", "it's", "a?b", "a    b\n c ",
+                   "x='say \"hi\"'", "<b/>"] {
+        let xml = format!("<?editor  {}?><a/>", data);
+        let d = match Document::from_string(&xml) {
+            Ok(d) => d,
+            Err(e) => panic!("parsing {:?} failed: {}", xml, e),
+        };
+        let pi = ProcessingInstruction::new("editor", data);
+        assert_eq!(d.children_nodes()[0], Node::ProcessingInstruction(pi));
+        assert!(d.to_string().contains(&format!("<?editor {}?>", data)));
+    }
+    let d = Document::from_string("<?editor ?><a/>").unwrap();
+    assert_eq!(d.children_nodes()[0],
+               Node::ProcessingInstruction(ProcessingInstruction::new("editor", "")));
+    assert!(Document::from_string("<?editor data").is_err());
+
+    // nor can it be written if it isn't well-formed
+    for &(target, data) in &[("p", "x?>y"), ("p", "?>"), ("1p", "x"), ("", "x"), ("a b", "")] {
+        let mut e = Element::new("a");
+        e.append_node(Node::ProcessingInstruction(ProcessingInstruction::new(target, data)));
+        let err = Writer::new(Vec::new(), &WriterConfig::default())
+            .write_element(&e, 0)
+            .unwrap_err();
+        assert_eq!(err.kind(), ::std::io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn comments() {
+    use super::*;
+    use super::parser::ParserConfig;
+
+    let xml = "<!-- generated -->\
+               <sprite><!-- keep the frames in order --><frame/>a<!--x-->b</sprite>";
+    let d = Document::from_string(xml).unwrap();
+    assert_eq!(d.children_nodes()[0], Node::Comment(" generated ".into()));
+    let root = d.get_root();
+    assert_eq!(root.children_nodes()[0],
+               Node::Comment(" keep the frames in order ".into()));
+    assert_eq!(root.text(), "ab");
+
+    let written = d.to_string();
+    assert!(written.contains("<!-- generated -->\n<sprite>"));
+    assert!(written.contains("<sprite><!-- keep the frames in order --><frame/>a<!--x-->b</sprite>"));
+    assert_eq!(Document::from_string(&written).unwrap().to_string(), written);
+
+    let config = ParserConfig {
+        ignore_comments: true,
+        ..ParserConfig::default()
+    };
+    let d = Document::from_string_with(xml, &config).unwrap();
+    assert_eq!(d.children_nodes().len(), 1);
+    assert_eq!(d.get_root().children_nodes().len(), 2);
+    assert_eq!(d.get_root().children_nodes()[1], Node::Text("ab".into()));
+
+    match Document::from_string("<a><!-- a -- b --></a>") {
+        Err(Error::Syntax(_, p)) => assert_eq!(p.column, 11),
+        other => panic!("expected a syntax error, got {:?}", other.err()),
+    }
+    assert!(Document::from_string("<a><!-- a ---></a>").is_err());
+    assert!(Document::from_string("<a><!-- a </a>").is_err());
+
+    // and they aren't written either
+    for c in &["a--b", "a-", "-"] {
+        let mut e = Element::new("a");
+        e.append_node(Node::Comment(c.to_string()));
+        let err = Writer::new(Vec::new(), &WriterConfig::default())
+            .write_element(&e, 0)
+            .unwrap_err();
+        assert_eq!(err.kind(), ::std::io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn namespaces() {
+    use super::*;
+    use super::namespace::*;
+    use super::parser::ParserConfig;
+
+    let svg = "http://www.w3.org/2000/svg";
+    let xlink = "http://www.w3.org/1999/xlink";
+    let xml = "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+                    xmlns:xlink=\"http://www.w3.org/1999/xlink\">\
+                 <use xlink:href=\"#a\" x=\"1\"/>\
+                 <meta xmlns=\"\"><s:title xmlns:s=\"http://www.w3.org/2000/svg\"/></meta>\
+               </svg>";
+    let d = Document::from_string(xml).unwrap();
+    let root = d.get_root();
+    assert_eq!(root.get_namespace_uri(), Some(svg));
+    assert_eq!(root.get_prefix(), None);
+
+    let use_ = root.get_child_ns(svg, "use").unwrap();
+    let href = use_.get_attribute_ns(xlink, "href").unwrap();
+    assert_eq!(href.get_name(), "xlink:href");
+    assert_eq!(href.get_value(), "#a");
+    assert_eq!(use_.get_attribute("x").unwrap().get_namespace_uri(), None);
+
+    // xmlns="" undeclares the default namespace
+    let meta = root.get_child("meta").unwrap();
+    assert_eq!(meta.get_namespace_uri(), None);
+    assert!(root.get_child_ns(svg, "meta").is_none());
+    let title = meta.get_child_ns(svg, "title").unwrap();
+    assert_eq!(title.get_qname(), &QName::new_ns(svg, "s:title"));
+    assert_eq!(title.get_local_name(), "title");
+
+    match Document::from_string("<a><b:c/></a>") {
+        Err(Error::UndeclaredPrefix(ref p, _)) => assert_eq!(p, "b"),
+        other => panic!("expected an undeclared prefix, got {:?}", other.err()),
+    }
+    assert!(Document::from_string("<a b:c=\"1\"/>").is_err());
+    assert!(Document::from_string("<a xmlns:b=\"\"/>").is_err());
+    assert!(Document::from_string("<a xmlns:xml=\"urn:x\"/>").is_err());
+    assert!(Document::from_string("<a xml:lang=\"en\"/>").is_ok());
+
+    // prefixes are only resolved when asked to
+    let config = ParserConfig {
+        namespace_aware: false,
+        ..ParserConfig::default()
+    };
+    let d = Document::from_string_with("<a><b:c/></a>", &config).unwrap();
+    assert_eq!(d.get_root().get_child("b:c").unwrap().get_namespace_uri(), None);
+}
+
+#[test]
+fn namespace_writing() {
+    use super::*;
+    use super::parser::ParserConfig;
+
+    let svg = "http://www.w3.org/2000/svg";
+    let xlink = "http://www.w3.org/1999/xlink";
+    let write = |e: Element| {
+        let mut written = Vec::new();
+        let config = WriterConfig {
+            indent: String::new(),
+            collapse_empty: true,
+            ..WriterConfig::default()
+        };
+        Writer::new(&mut written, &config).write_element(&e, 0).unwrap();
+        String::from_utf8(written).unwrap().replace('\n', "")
+    };
+
+    // declarations are added where the namespaces are first used
+    let mut root = Element::new_ns(svg, "svg");
+    let mut use_ = Element::new_ns(svg, "use");
+    use_.append_attribute(Attribute::new_ns(xlink, "xlink:href", "#a"));
+    root.append_child(use_);
+    let mut image = Element::new_ns(svg, "image");
+    image.append_attribute(Attribute::new_ns(xlink, "href", "b.png"));
+    root.append_child(image);
+    root.append_child(Element::new("desc"));
+    assert_eq!(write(root),
+               "<svg xmlns=\"http://www.w3.org/2000/svg\">\
+                <use xmlns:xlink=\"http://www.w3.org/1999/xlink\" xlink:href=\"#a\"/>\
+                <image xmlns:ns0=\"http://www.w3.org/1999/xlink\" ns0:href=\"b.png\"/>\
+                <desc xmlns=\"\"/>\
+                </svg>");
+
+    // in-scope prefixes are reused and redeclarations dropped
+    let mut root = Element::new_ns(svg, "s:svg");
+    root.append_attribute(Attribute::new("xmlns:s", svg));
+    let mut g = Element::new_ns(svg, "g");
+    g.append_attribute(Attribute::new("xmlns:s", svg));
+    g.append_child(Element::new_ns(svg, "s:rect"));
+    root.append_child(g);
+    assert_eq!(write(root),
+               "<s:svg xmlns:s=\"http://www.w3.org/2000/svg\">\
+                <s:g><s:rect/></s:g>\
+                </s:svg>");
+
+    // a prefix can't be rebound on the element using it
+    let mut a = Element::new_ns("urn:a", "p:a");
+    a.append_attribute(Attribute::new_ns("urn:b", "p:b", "1"));
+    assert_eq!(write(a),
+               "<p:a xmlns:p=\"urn:a\" xmlns:ns0=\"urn:b\" ns0:b=\"1\"/>");
+
+    // parsed documents are written back as they were
+    let xml = "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+               xmlns:xlink=\"http://www.w3.org/1999/xlink\">\
+               <use xlink:href=\"#a\"/></svg>";
+    let d = Document::from_string(xml).unwrap();
+    assert_eq!(write(d.get_root().clone()), xml);
+
+    // an xmlns attribute on a name without a namespace is
+    // kept as it is, with the unqualified names under it
+    let mut root = Element::new("svg");
+    root.set_attribute("xmlns", svg);
+    root.append_child(Element::new("rect"));
+    assert_eq!(write(root),
+               "<svg xmlns=\"http://www.w3.org/2000/svg\"><rect/></svg>");
+
+    // so documents read without namespaces are written back as they were
+    let config = ParserConfig {
+        namespace_aware: false,
+        ..ParserConfig::default()
+    };
+    let xml = "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+               xmlns:xlink=\"http://www.w3.org/1999/xlink\">\
+               <g xmlns=\"http://www.w3.org/2000/svg\">\
+               <use xlink:href=\"#a\"/><rect/></g></svg>";
+    let d = Document::from_string_with(xml, &config).unwrap();
+    let written = write(d.get_root().clone());
+    assert_eq!(written, xml);
+    let again = Document::from_string_with(&written, &config).unwrap();
+    assert!(again.get_root() == d.get_root());
+}
+
+#[test]
+fn name_validation() {
+    use super::*;
+    use super::tokenizer::is_name;
+
+    let d = Document::from_string("<my-tag _x=\"1\" a.b=\"2\" \u{e9}t\u{e9}=\"3\">\
+                                   <_x/><a.b/><x\u{b7}1/></my-tag>").unwrap();
+    let root = d.get_root();
+    assert_eq!(root.get_name(), "my-tag");
+    assert_eq!(root.get_attribute("a.b").unwrap().get_value(), "2");
+    assert_eq!(root.get_child_count(), 3);
+
+    let expect_invalid = |xml: &str, c: char, column: usize| {
+        match Document::from_string(xml) {
+            Err(Error::InvalidCharacter(found, p)) => {
+                assert_eq!((found, p.column), (c, column), "in {}", xml);
+            }
+            other => panic!("{} gave {:?}", xml, other.err()),
+        }
+    };
+    expect_invalid("<1abc/>", '1', 2);
+    expect_invalid("<a-b.c$/>", '$', 7);
+    expect_invalid("<a -x=\"1\"/>", '-', 4);
+    expect_invalid("<a></-a>", '-', 6);
+    expect_invalid("<!DOCTYPE 9a><a/>", '9', 11);
+
+    assert!(is_name("x:y-z.1"));
+    assert!(!is_name("-x"));
+    assert!(!is_name(""));
+}
+
+#[test]
+fn well_formedness() {
+    use super::*;
+    use super::parser::ParserConfig;
+
+    let strict = ParserConfig {
+        strict: true,
+        ..ParserConfig::default()
+    };
+    let parse = |xml: &str| Document::from_string_with(xml, &strict);
+
+    assert!(parse("<?xml version=\"1.0\"?>\n<!DOCTYPE a>\n<!-- c --><a b=\"1\" c=\"2\">\
+                   <![CDATA[x]]>y</a>\n<?pi?>\n").is_ok());
+    // tolerated unless strict
+    assert!(Document::from_string("<a><b>").is_ok());
+    assert!(Document::from_string("<a/><b/>").is_ok());
+    assert!(Document::from_string("<a x=\"1\" x=\"2\"/>").is_ok());
+
+    for bad in &["<a><b></b>",
+                 "",
+                 "<!-- only a comment -->",
+                 "<a/><b/>",
+                 "<a x=\"1\" x=\"2\"/>",
+                 "<a xmlns:p=\"urn:x\" xmlns:q=\"urn:x\" p:x=\"1\" q:x=\"2\"/>",
+                 "<a x=\"<\"/>",
+                 "<a>]]></a>",
+                 "<a>\u{1}</a>",
+                 "<a><!-- \u{fffe} --></a>",
+                 "<a/><!DOCTYPE a>",
+                 "<!DOCTYPE a><!DOCTYPE a><a/>",
+                 "<a><!DOCTYPE a></a>",
+                 "<a><?XML x?></a>",
+                 "<a/>text",
+                 "< a/>",
+                 "<a></ a>",
+                 "<a></>",
+                 "<a></ >",
+                 "<? pi?><a/>",
+                 "<?pi/x?><a/>",
+                 "<a x=\"1\"y=\"2\"/>",
+                 "<?xml version=\"1.0\"encoding=\"UTF-8\"?><a/>",
+                 "<a/><?xml-foo?>",
+                 "<?pi \u{1}?><a/>",
+                 "<!DOCTYPE a SYSTEM \"\u{1}\"><a/>",
+                 "<!DOCTYPE a [<!ENTITY e '\u{1}'>]><a/>"] {
+        assert!(parse(bad).is_err(), "accepted {:?}", bad);
+    }
+    for good in &["<a\n  x = \"1\"\ty='2' ></a >",
+                  "<?xml-stylesheet href=\"a.xsl\"?><a/>",
+                  "<?pi \n data ?><a/>"] {
+        assert!(parse(good).is_ok(), "rejected {:?}", good);
+    }
+    // all of them are tolerated unless strict
+    for lenient in &["< a/>", "<a></ a>", "<a></>", "<? pi?><a/>", "<a x=\"1\"y=\"2\"/>"] {
+        assert!(Document::from_string(lenient).is_ok(), "rejected {:?}", lenient);
+    }
+
+    match parse("<a x='1' y='ab<'/>") {
+        Err(Error::InvalidCharacter(c, p)) => assert_eq!((c, p.column), ('<', 15)),
+        other => panic!("expected an invalid character, got {:?}", other.err()),
+    }
+
+    match parse("<a>\n  x\u{b}</a>") {
+        Err(Error::InvalidCharacter(c, p)) => {
+            assert_eq!((c, p.line, p.column), ('\u{b}', 2, 4));
+        }
+        other => panic!("expected an invalid character, got {:?}", other.err()),
+    }
+
+    match parse("<!DOCTYPE a [\n<!ENTITY e 'x\u{1}'>]><a/>") {
+        Err(Error::InvalidCharacter(c, p)) => {
+            assert_eq!((c, p.line, p.column), ('\u{1}', 2, 14));
+        }
+        other => panic!("expected an invalid character, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn mutable_dom() {
+    use super::*;
+
+    let xml = "<level><!-- layout --><rooms><room id=\"1\"/><room id=\"2\"/></rooms>\
+               <spawn x=\"0\"/></level>";
+    let mut d = Document::from_string(xml).unwrap();
+    {
+        let root = d.get_root_mut();
+        root.get_child_mut("spawn").unwrap().set_attribute("x", "12");
+        root.get_child_mut("spawn").unwrap().set_attribute("y", "4");
+
+        let rooms = root.get_child_mut("rooms").unwrap();
+        let mut room = Element::new("room");
+        room.set_attribute("id", "0");
+        rooms.insert_before(0, Node::Element(room));
+        let mut room = Element::new("room");
+        room.set_attribute("id", "3");
+        rooms.insert_after(2, Node::Element(room));
+        let old = rooms.replace_child(1, Node::Comment(" was room 1 ".into()));
+        assert_eq!(old.unwrap().as_element().unwrap().get_attribute("id").unwrap().get_value(),
+                   "1");
+        rooms.retain(|n| {
+            n.as_element().is_none_or(|e| e.get_attribute("id").unwrap().get_value() != "2")
+        });
+
+        let index = root.position_of("spawn").unwrap();
+        assert_eq!(index, 2);
+        let spawn = root.remove_child(index).unwrap();
+        root.insert_before(0, spawn);
+        assert!(root.remove_child(10).is_none());
+        assert!(root.get_child_mut("spawn").unwrap().remove_attribute("y").is_some());
+    }
+    assert_eq!(d.get_root().children_nodes().len(), 3);
+
+    let mut written = Vec::new();
+    let config = WriterConfig {
+        indent: String::new(),
+        collapse_empty: true,
+        write_declaration: false,
+        ..WriterConfig::default()
+    };
+    d.write_with(&mut written, &config).unwrap();
+    assert_eq!(String::from_utf8(written).unwrap().replace('\n', ""),
+               "<level><spawn x=\"12\"/><!-- layout --><rooms>\
+                <room id=\"0\"/><!-- was room 1 --><room id=\"3\"/>\
+                </rooms></level>");
+
+    let mut rooms = d.get_root_mut().remove_child_named("rooms").unwrap();
+    assert_eq!(rooms.take_children().len(), 3);
+    assert_eq!(rooms.children_nodes().len(), 0);
+
+    let old = d.set_root(rooms);
+    assert_eq!(old.unwrap().get_name(), "level");
+    assert_eq!(d.get_root().get_name(), "rooms");
+}
+
+#[test]
+fn borrowing_iterators() {
+    use super::Document;
+
+    let mut d = Document::from_file("res/player_light.sprite").unwrap();
+    let frames = d.get_root()
+        .get_child("animations").unwrap()
+        .get_child("animation").unwrap();
+    let names: Vec<&str> = frames.children_named("frame")
+        .map(|f| f.get_attribute("name").unwrap().get_value())
+        .collect();
+    assert_eq!(names, vec!["player-0-0", "player-0-1", "player-0-2", "player-0-3"]);
+    assert_eq!(frames.children().count(), frames.get_child_count());
+
+    let last = frames.children().last().unwrap();
+    let attributes: Vec<&str> = last.attributes().map(|a| a.get_name()).collect();
+    assert_eq!(attributes, vec!["name", "duration"]);
+
+    {
+        let frames = d.get_root_mut()
+            .get_child_mut("animations").unwrap()
+            .get_child_mut("animation").unwrap();
+        for frame in frames.children_named_mut("frame") {
+            for a in frame.attributes_mut() {
+                if a.get_name() == "duration" {
+                    a.set_value("0.5");
+                }
+            }
+        }
+        for frame in frames.children_mut() {
+            frame.set_attribute("visited", "true");
+        }
+    }
+    let frames = d.get_root()
+        .get_child("animations").unwrap()
+        .get_child("animation").unwrap();
+    for frame in frames.children() {
+        assert_eq!(frame.get_attribute("visited").unwrap().get_value(), "true");
+    }
+    let durations: Vec<&str> = frames.children()
+        .filter_map(|f| f.get_attribute("duration"))
+        .map(|a| a.get_value())
+        .collect();
+    assert_eq!(durations, vec!["0.5", "0.5"]);
+}
+
+#[test]
+fn traversal() {
+    use super::Document;
+    use super::traversal::Edge;
+
+    let d = Document::from_file("res/player_light.sprite").unwrap();
+    let root = d.get_root();
+
+    let frames: Vec<&str> = root.descendants()
+        .filter(|e| e.get_name() == "frame")
+        .map(|e| e.get_attribute("name").unwrap().get_value())
+        .collect();
+    assert_eq!(frames, vec!["player-0-0.png", "player-0-0",
+                            "player-0-1", "player-0-2", "player-0-3"]);
+    assert_eq!(root.descendants().count(), 13);
+
+    let d = Document::from_string("<a><b><c/></b>text<d/></a>").unwrap();
+    let edges: Vec<String> = d.get_root()
+        .traverse()
+        .map(|edge| match edge {
+            Edge::Start(e) => format!("<{}>", e.get_name()),
+            Edge::End(e) => format!("</{}>", e.get_name()),
+        })
+        .collect();
+    assert_eq!(edges.concat(), "<a><b><c></c></b><d></d></a>");
+
+    let names: Vec<&str> = d.get_root().breadth_first().map(|e| e.get_name()).collect();
+    assert_eq!(names, vec!["b", "d", "c"]);
+}
+
+#[test]
+fn arena_document() {
+    use super::Document;
+    use super::arena::*;
+
+    let xml = "<!-- sprite --><sprite><frames><frame name=\"a\"/><frame name=\"b\"/>\
+               <frame name=\"c\"/></frames>text<meta/></sprite>";
+    let mut d = ArenaDocument::from_string(xml).unwrap();
+    let root = d.root().unwrap();
+    assert_eq!(d.element(root).unwrap().get_name(), "sprite");
+    assert_eq!(d.parent(root), Some(d.document()));
+
+    let frames = d.first_child(root).unwrap();
+    let names: Vec<&str> = d.children(frames)
+        .map(|id| d.element(id).unwrap().get_attribute("name").unwrap().get_value())
+        .collect();
+    assert_eq!(names, vec!["a", "b", "c"]);
+
+    let b = d.next_sibling(d.first_child(frames).unwrap()).unwrap();
+    assert_eq!(d.ancestors(b).collect::<Vec<_>>(), vec![frames, root, d.document()]);
+    assert_eq!(d.previous_sibling(b), d.first_child(frames));
+    assert_eq!(d.text(root), "text");
+    assert_eq!(d.descendants(root).count(), 6);
+
+    let edges: Vec<Edge> = d.traverse(frames).take(3).collect();
+    assert_eq!(edges, vec![Edge::Start(frames),
+                           Edge::Start(d.first_child(frames).unwrap()),
+                           Edge::End(d.first_child(frames).unwrap())]);
+
+    // nodes are moved in place, handles stay valid
+    let meta = d.last_child(root).unwrap();
+    d.insert_before(b, meta);
+    assert_eq!(d.parent(meta), Some(frames));
+    let c = d.last_child(frames).unwrap();
+    d.prepend(frames, c);
+    d.detach(b);
+    assert_eq!(d.parent(b), None);
+    let new = d.new_element("frame");
+    d.element_mut(new).unwrap().set_attribute("name", "d");
+    d.insert_after(meta, new);
+    d.append(new, b);
+
+    let owned = d.to_document();
+    let frames = owned.get_root().get_child("frames").unwrap();
+    let names: Vec<&str> = frames.children().map(|e| e.get_name()).collect();
+    assert_eq!(names, vec!["frame", "frame", "meta", "frame"]);
+    assert_eq!(frames.children().last().unwrap().get_child_count(), 1);
+    assert!(owned.to_string().starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                                           <!-- sprite -->\n<sprite>"));
+
+    let copy = ArenaDocument::from_document(&Document::from_string(xml).unwrap());
+    assert_eq!(copy.to_string(), Document::from_string(xml).unwrap().to_string());
+}
+
+#[test]
+fn deep_nesting() {
+    use super::Document;
+    use super::arena::ArenaDocument;
+    use super::parser::ParserConfig;
+    use super::writer::WriterConfig;
+
+    let depth = 20000;
+    let xml = format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+    let d = Document::from_string(&xml).unwrap();
+
+    let mut e = d.get_root();
+    let mut levels = 1;
+    while let Some(child) = e.get_first_child() {
+        e = child;
+        levels += 1;
+    }
+    assert_eq!(levels, depth);
+    assert_eq!(d.get_root().descendants().count(), depth - 1);
+
+    let arena = ArenaDocument::from_string(&xml).unwrap();
+    let root = arena.root().unwrap();
+    let deepest = arena.descendants(root).last().unwrap();
+    assert_eq!(arena.ancestors(deepest).count(), depth);
+    assert_eq!(arena.to_document().get_root().descendants().count(), depth - 1);
+
+    // writing, cloning and comparing don't recurse either,
+    // without indentation the output stays linear in size
+    let config = WriterConfig {
+        indent: String::new(),
+        write_declaration: false,
+        ..WriterConfig::default()
+    };
+    let mut buf = Vec::new();
+    d.write_with(&mut buf, &config).unwrap();
+    let written = String::from_utf8(buf).unwrap();
+    assert_eq!(written.matches("<a>").count(), depth);
+    let trim = ParserConfig {
+        trim_whitespace: true,
+        ..ParserConfig::default()
+    };
+    let again = Document::from_string_with(&written, &trim).unwrap();
+    assert!(again.get_root() == d.get_root());
+
+    let copy = d.get_root().clone();
+    assert_eq!(copy.descendants().count(), depth - 1);
+    assert!(copy == *d.get_root());
+    let mut other = copy.clone();
+    other.get_first_child_mut().unwrap().set_attribute("x", "1");
+    assert!(other != copy);
+}
@@ -1,3 +1,12 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::iter::Map;
+use std::str::{self, Chars};
+
+use super::error::{Error, Position};
+
 // tokens:
 //
 //  Left: <
@@ -5,12 +14,17 @@
 //  ClosingLeft: </
 //  ClosingRight: />
 //  PILeft: <?
-//  PIRight: ?>
+//  PIRight: ?>, the target of a PI is read as Text,
+//      followed by its data as a single Text
 //  MDLeft: <!
-//  Value(key, value): key="value"
+//  Value(key, value): key="value", at the position of value
 //  Text(text): text
-//  Comment: <!-- -->
-    
+//  Comment(text): <!--text-->
+//  CData(text): <![CDATA[text]]>
+//  Subset(text): [text] inside <!DOCTYPE>
+//  Space: whitespace inside tags and PIs, which only
+//      matters for checking well-formedness
+
 #[derive(Clone, Debug)]
 pub enum Token {
     Left,
@@ -22,275 +36,597 @@ pub enum Token {
     MDLeft,
     Value(String, String),
     Text(String),
-    Comment,
+    Comment(String),
+    CData(String),
+    Subset(String),
+    Space,
 }
 
-pub fn invalid_token(last: &char,
-                     s: &char) -> Result<Vec<Token>, String> {
-    Err(format!("Invalid Token: {}{}", last, s))
+// what the tokenizer is currently reading,
+// whitespace is only significant in content
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Context {
+    Content,
+    Tag,
+    // the target of a processing instruction is a name,
+    // the data after it can be anything
+    PITarget,
+    PI,
+    // the pseudo-attributes of an XML declaration
+    // are read like the attributes of a tag
+    Declaration,
+    MD,
 }
 
-pub fn tokenize(s: &str) -> Result<Vec<Token>, String> {
-    let s = strip(s);
-    let mut acc = String::new();
+pub struct Tokenizer<I: Iterator<Item = io::Result<char>>> {
+    chars: I,
+    // characters that were peeked at, with
+    // the number of bytes they took in the source
+    lookahead: VecDeque<(char, usize)>,
+    // character read after a \r that wasn't \n
+    after_cr: Option<char>,
+    context: Context,
+    // position of the next character
+    position: Position,
+    // position where the last token started
+    token_start: Position,
+    // error from the input, reported
+    // in place of the next token
+    io_error: Option<io::Error>,
+    failed: bool,
+    // whether the first character was read, which
+    // is skipped if it is a byte order mark
+    started: bool,
+}
 
-    let mut tokens = Vec::new();
-    let mut last = Vec::new();
-    let mut last_space = false;
+impl<I: Iterator<Item = io::Result<char>>> Iterator for Tokenizer<I> {
+    type Item = Result<(Token, Position), Error>;
 
-    let mut reading_value = false;
-    let mut value_name = String::new();
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.next_token() {
+            Ok(Some(token)) => Some(Ok((token, self.token_start))),
+            Ok(None) => None,
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
 
-    let mut reading_comment = false;
+impl<I: Iterator<Item = io::Result<char>>> Tokenizer<I> {
+    pub fn new(chars: I) -> Tokenizer<I> {
+        Tokenizer {
+            chars,
+            lookahead: VecDeque::new(),
+            after_cr: None,
+            context: Context::Content,
+            position: Position::new(),
+            token_start: Position::new(),
+            io_error: None,
+            failed: false,
+            started: false,
+        }
+    }
 
-    for c in s.chars() {
-        // for reading key/value pairs
-        if reading_value {
-            if c == '"' || c == '\'' {
-                tokens.push(Token::Value(value_name.clone(),
-                                         acc.clone()));
-                value_name.clear();
-                acc.clear();
-                reading_value = false;
-                last_space = true;
-                continue;
-            }
-            else {
-                acc.push(c);
-                continue;
+    // position of the next character to be read
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    // position where the last returned token started
+    pub fn token_start(&self) -> Position {
+        self.token_start
+    }
+
+    // returns the next token, or None at the end of input
+    pub fn next_token(&mut self) -> Result<Option<Token>, Error> {
+        let token = self.read_token();
+        match self.io_error.take() {
+            Some(e) => Err(Error::Io(e)),
+            None => token,
+        }
+    }
+
+    fn read_token(&mut self) -> Result<Option<Token>, Error> {
+        if self.context == Context::PI {
+            return self.read_pi_data().map(Some);
+        }
+        if self.context != Context::Content {
+            self.token_start = self.position;
+            if self.skip_whitespace() && self.context != Context::MD {
+                return Ok(Some(Token::Space));
             }
+            self.token_start = self.position;
+            return self.read_in_tag().map(Some);
         }
 
-        if reading_comment {
-            if !last.is_empty() {
-                if last.len() == 2 {
-                    if last[0] == '-' && last[1] == '-' && c == '>' {
-                        reading_comment = false;
-                    }
-                }
+        // peeking first, so a byte order mark
+        // is skipped before the token starts
+        let next = self.peek(0);
+        self.token_start = self.position;
+        match next {
+            None => Ok(None),
+            Some('<') => self.read_markup_start().map(Some),
+            Some(_) => Ok(Some(Token::Text(self.read_text()))),
+        }
+    }
 
-                if last[0] == '-' && c == '-' {
-                    last.push(c);
+    // reads character data up to the next markup,
+    // keeping all whitespace
+    fn read_text(&mut self) -> String {
+        let mut acc = String::new();
+        while let Some(c) = self.peek(0) {
+            if c == '<' {
+                break;
+            }
+            self.next_char();
+            acc.push(c);
+        }
+        acc
+    }
+
+    // reads the start of markup after a '<', comments
+    // and CDATA sections are read as a whole
+    fn read_markup_start(&mut self) -> Result<Token, Error> {
+        self.next_char();
+        match self.peek(0) {
+            Some('/') => {
+                self.next_char();
+                self.context = Context::Tag;
+                Ok(Token::ClosingLeft)
+            }
+            Some('?') => {
+                self.next_char();
+                self.context = Context::PITarget;
+                Ok(Token::PILeft)
+            }
+            Some('!') => {
+                self.next_char();
+                if self.starts_with("--") {
+                    return self.read_comment();
                 }
-                else {
-                    last.clear();
+                if self.starts_with("[CDATA[") {
+                    return self.read_cdata();
                 }
+                self.context = Context::MD;
+                Ok(Token::MDLeft)
+            }
+            Some(_) => {
+                self.context = Context::Tag;
+                Ok(Token::Left)
             }
+            None => Err(Error::UnexpectedEof(self.position)),
+        }
+    }
 
-            if c == '-' {
-                last.push(c);
+    // reads the text of a comment, which
+    // must not contain "--"
+    fn read_comment(&mut self) -> Result<Token, Error> {
+        self.next_char();
+        self.next_char();
+        let mut acc = String::new();
+        loop {
+            if self.starts_with("--") {
+                if !self.starts_with("-->") {
+                    return Err(Error::Syntax("'--' not allowed in comments".into(),
+                                             self.position));
+                }
+                self.next_char();
+                self.next_char();
+                self.next_char();
+                return Ok(Token::Comment(acc));
             }
+            match self.next_char() {
+                Some(c) => acc.push(c),
+                None => return Err(Error::UnexpectedEof(self.position)),
+            }
+        }
+    }
 
-            continue;
+    // reads the content of a CDATA section as it is
+    fn read_cdata(&mut self) -> Result<Token, Error> {
+        for _ in 0.."[CDATA[".len() {
+            self.next_char();
         }
+        let mut acc = String::new();
+        loop {
+            if self.starts_with("]]>") {
+                self.next_char();
+                self.next_char();
+                self.next_char();
+                return Ok(Token::CData(acc));
+            }
+            match self.next_char() {
+                Some(c) => acc.push(c),
+                None => return Err(Error::UnexpectedEof(self.position)),
+            }
+        }
+    }
+
+    // reads names, attributes and the end of tags
+    fn read_in_tag(&mut self) -> Result<Token, Error> {
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => return Err(Error::UnexpectedEof(self.position)),
+        };
 
-        // match next token
         match c {
-            '<' => {
-                last_space = false;
-                if !acc.is_empty() {
-                    tokens.push(Token::Text(acc.clone()));
-                    acc.clear();
-                }
-                if last.is_empty() {
-                    last.push(c);
-                }
-                else {
-                    return invalid_token(&last[0], &c);
-                }
-            }
             '>' => {
-                last_space = false;
-                if !acc.is_empty() {
-                    tokens.push(Token::Text(acc.clone()));
-                    acc.clear();
-                }
-                if last.is_empty() {
-                    tokens.push(Token::Right);
-                }
-                else if last[0] == '/' {
-                    tokens.push(Token::ClosingRight);
-                    last.clear();
-                }
-                else if last[0] == '?' {
-                    tokens.push(Token::PIRight);
-                    last.clear();
-                }
-                else {
-                    return invalid_token(&last[0], &c);
-                }
+                self.next_char();
+                self.context = Context::Content;
+                Ok(Token::Right)
+            }
+            '/' if self.context == Context::Tag => {
+                self.next_char();
+                self.expect('>')?;
+                self.context = Context::Content;
+                Ok(Token::ClosingRight)
+            }
+            '?' if self.context == Context::PITarget || self.context == Context::Declaration => {
+                self.next_char();
+                self.expect('>')?;
+                self.context = Context::Content;
+                Ok(Token::PIRight)
+            }
+            '[' if self.context == Context::MD => {
+                self.read_subset()
             }
-            '/' => {
-                last_space = false;
-                if !acc.is_empty() {
-                    tokens.push(Token::Text(acc.clone()));
-                    acc.clear();
+            '"' | '\'' => {
+                // literal without a name, as in markup declarations
+                let value = self.read_quoted()?;
+                Ok(Token::Value(String::new(), value))
+            }
+            _ => {
+                let name = self.read_name()?;
+                if self.context == Context::PITarget {
+                    self.context = if name == "xml" { Context::Declaration } else { Context::PI };
+                    return Ok(Token::Text(name));
                 }
-                if last.is_empty() {
-                    last.push(c);
+                // whitespace after the name is only
+                // skipped here if an '=' follows it
+                let mut i = 0;
+                while self.peek(i).is_some_and(is_whitespace) {
+                    i += 1;
                 }
-                else if last[0] == '<' {
-                    tokens.push(Token::ClosingLeft);
-                    last.clear();
+                if self.peek(i) != Some('=') {
+                    return Ok(Token::Text(name));
                 }
-                else {
-                    return invalid_token(&last[0], &c);
+                self.skip_whitespace();
+
+                self.next_char();
+                self.skip_whitespace();
+                match self.peek(0) {
+                    Some('"') | Some('\'') => {
+                        let value = self.read_quoted()?;
+                        Ok(Token::Value(name, value))
+                    }
+                    Some(_) => {
+                        Err(Error::Syntax(
+                            format!("Expected quoted value for {}", name),
+                            self.position))
+                    }
+                    None => Err(Error::UnexpectedEof(self.position)),
                 }
             }
-            '"' | '\'' => {
-                last_space = false;
-                reading_value = true;
+        }
+    }
 
-                if !acc.is_empty() {
-                    tokens.push(Token::Text(acc.clone()));
-                    acc.clear();
-                }
+    // reads the data of a processing instruction as it is
+    // written, up to the "?>" that ends it, the whitespace
+    // separating it from the target isn't part of it
+    fn read_pi_data(&mut self) -> Result<Token, Error> {
+        self.token_start = self.position;
+        if self.skip_whitespace() {
+            return Ok(Token::Space);
+        }
+        if self.starts_with("?>") {
+            self.next_char();
+            self.next_char();
+            self.context = Context::Content;
+            return Ok(Token::PIRight);
+        }
 
-                if !last.is_empty() {
-                    return invalid_token(&last[0], &c);
-                }
+        let mut acc = String::new();
+        while !self.starts_with("?>") {
+            match self.next_char() {
+                Some(c) => acc.push(c),
+                None => return Err(Error::UnexpectedEof(self.position)),
             }
-            '=' => {
-                last_space = false;
-                if !acc.is_empty() {
-                    value_name = acc.clone();
-                    acc.clear();
-                }
-                if !last.is_empty() {
-                    return invalid_token(&last[0], &c);
-                }
+        }
+        Ok(Token::Text(acc))
+    }
+
+    // reads the internal subset of a document type
+    // declaration, up to the matching ']'
+    fn read_subset(&mut self) -> Result<Token, Error> {
+        self.next_char();
+        let mut acc = String::new();
+        let mut quote = None;
+        loop {
+            // quotes and ']' in comments and PIs are part of
+            // them, they don't start literals or end the subset
+            let end = if quote.is_some() {
+                None
             }
-            ' ' => {
-                last_space = true;
+            else if self.starts_with("<!--") {
+                Some("-->")
             }
-            '?' => {
-                if !last.is_empty() {
-                    if last[0] == '<' {
-                        last_space = false;
-                        last.clear();
-                        tokens.push(Token::PILeft);
-                        acc.clear();
-                    }
-                    else {
-                        return invalid_token(&last[0], &c);
+            else if self.starts_with("<?") {
+                Some("?>")
+            }
+            else {
+                None
+            };
+            if let Some(end) = end {
+                while !self.starts_with(end) {
+                    match self.next_char() {
+                        Some(c) => acc.push(c),
+                        None => return Err(Error::UnexpectedEof(self.position)),
                     }
                 }
-                else {
-                    last.push(c);
-                    acc.clear();
-                }
             }
-            '!' => {
-                if last.is_empty() {
-                    return invalid_token(&last[0], &c);
-                }
-                else if last[0] == '<' {
-                    last_space = false;
-                    last.push(c);
-                    acc.clear();
-                }
+
+            let c = match self.next_char() {
+                Some(c) => c,
+                None => return Err(Error::UnexpectedEof(self.position)),
+            };
+            match c {
+                ']' if quote.is_none() => return Ok(Token::Subset(acc)),
+                '"' | '\'' if quote.is_none() => quote = Some(c),
+                _ if quote == Some(c) => quote = None,
+                _ => {}
             }
-            '-' => {
-                if !last.is_empty() {
-                    if last.len() == 3 {
-                        if last[0] == '<'
-                           && last[1] == '!' 
-                           && last[2] == '-' {
-                               last.clear();
-                               reading_comment = true;
-                           }
-                    }
-                    else if last.len() == 2 {
-                        if last[0] == '<'
-                           && last[1] == '!' {
-                               last.push(c);
-                           }
-                    }
-                    else {
-                        return invalid_token(&last[0], &c);
-                    }
-                }
+            acc.push(c);
+        }
+    }
+
+    // reads a name, which has to match the Name production
+    fn read_name(&mut self) -> Result<String, Error> {
+        let mut acc = String::new();
+        while let Some(c) = self.peek(0) {
+            if is_whitespace(c) || "=<>/?\"'[]".contains(c) {
+                break;
             }
-            _ => {
-                if last.len() == 2 {
-                    if last[0] == '<' && last[1] == '!' {
-                        tokens.push(Token::MDLeft);
-                    }
-                }
+            let valid = if acc.is_empty() { is_name_start_char(c) } else { is_name_char(c) };
+            if !valid {
+                return Err(Error::InvalidCharacter(c, self.position));
+            }
+            self.next_char();
+            acc.push(c);
+        }
+        match self.peek(0) {
+            Some(c) if acc.is_empty() => Err(Error::InvalidCharacter(c, self.position)),
+            None if acc.is_empty() => Err(Error::UnexpectedEof(self.position)),
+            _ => Ok(acc),
+        }
+    }
 
-                if !last.is_empty() {
-                    if last[0] == '<' {
-                        tokens.push(Token::Left);
-                        last.clear();
-                    }
-                }
-                if c.is_alphanumeric() {
-                    if last_space && !acc.is_empty() {
-                        last_space = false;
-                        tokens.push(Token::Text(acc.clone()));
-                        acc.clear();
+    // reads a quoted value as it is written, the token
+    // starts after the quote so that references in
+    // the value can be located
+    fn read_quoted(&mut self) -> Result<String, Error> {
+        let quote = self.next_char();
+        self.token_start = self.position;
+        let mut acc = String::new();
+        loop {
+            match self.next_char() {
+                Some(c) if Some(c) == quote => return Ok(acc),
+                Some(c) => acc.push(c),
+                None => return Err(Error::UnexpectedEof(self.position)),
+            }
+        }
+    }
 
-                        acc.push(c);
-                    }
-                    else {
-                        last_space = false;
-                        acc.push(c);
-                    }
-                }
-                else {
-                    if !acc.is_empty() {
-                        tokens.push(Token::Text(acc.clone()));
-                        acc.clear();
-                    }
-                }
+    // consumes c, or fails if the next character is different
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        match self.peek(0) {
+            Some(next) if next == c => {
+                self.next_char();
+                Ok(())
             }
+            Some(next) => Err(Error::InvalidCharacter(next, self.position)),
+            None => Err(Error::UnexpectedEof(self.position)),
         }
     }
-    Ok(tokens)
-}
 
-pub fn string_from_file(path: &str) -> Option<String> {
-    use std::fs::File;
-    use std::io::Read;
-    let mut file = match File::open(path) {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Error while reading file at {}: {}",
-                     path,
-                     e);
-            return None;
+    // returns whether there was any whitespace
+    fn skip_whitespace(&mut self) -> bool {
+        let mut skipped = false;
+        while let Some(c) = self.peek(0) {
+            if !is_whitespace(c) {
+                break;
+            }
+            self.next_char();
+            skipped = true;
         }
-    };
-    let mut string = String::new();
-    match file.read_to_string(&mut string) {
-        Ok(_) => {}
-        Err(e) => {
-            println!(
-                "Error while reading string {} to file: {}",
-                string,
-                e);
+        skipped
+    }
+
+    fn starts_with(&mut self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.peek(i) == Some(c))
+    }
+
+    fn peek(&mut self, i: usize) -> Option<char> {
+        while self.lookahead.len() <= i {
+            match self.read_char() {
+                Some(c) => self.lookahead.push_back(c),
+                None => return None,
+            }
+        }
+        Some(self.lookahead[i].0)
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let (c, len) = match self.lookahead.pop_front() {
+            Some(c) => c,
+            None => self.read_char()?,
+        };
+        self.position.advance(c, len);
+        Some(c)
+    }
+
+    // reads from the input, normalizing line endings to \n,
+    // errors are kept and the input treated as ended
+    fn read_char(&mut self) -> Option<(char, usize)> {
+        let c = match self.after_cr.take() {
+            Some(c) => c,
+            None => self.input_char()?,
+        };
+        if c != '\r' {
+            return Some((c, c.len_utf8()));
+        }
+        match self.input_char() {
+            Some('\n') => Some(('\n', 2)),
+            Some(c) => {
+                self.after_cr = Some(c);
+                Some(('\n', 1))
+            }
+            None => Some(('\n', 1)),
+        }
+    }
+
+    fn input_char(&mut self) -> Option<char> {
+        if self.io_error.is_some() {
             return None;
         }
+        match self.chars.next()? {
+            Ok('\u{feff}') if !self.started => {
+                // only the bytes of the byte order
+                // mark are counted, not a column
+                self.started = true;
+                self.position.offset += '\u{feff}'.len_utf8();
+                self.input_char()
+            }
+            Ok(c) => {
+                self.started = true;
+                Some(c)
+            }
+            Err(e) => {
+                self.io_error = Some(e);
+                None
+            }
+        }
     }
-    Some(string)
 }
 
-pub fn strip(s: &str) -> String {
-    let mut stripped = String::new();
-    let mut last_space = false;
-    for c in s.chars() {
-        if !"\n\t".to_string().contains(c) {
-            if c == ' ' {
-                if !last_space {
-                last_space = true;
-                }
-                else { continue; }
-            }
-            else { last_space = false; }
+// decodes UTF-8 characters from a buffered reader
+// as they are needed
+pub struct CharReader<R: BufRead> {
+    inner: R,
+}
+
+impl<R: BufRead> CharReader<R> {
+    pub fn new(inner: R) -> CharReader<R> {
+        CharReader {
+            inner,
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = match self.inner.fill_buf()?.first() {
+            Some(&b) => b,
+            None => return Ok(None),
+        };
+        self.inner.consume(1);
+        Ok(Some(byte))
+    }
+}
+
+impl<R: BufRead> Iterator for CharReader<R> {
+    type Item = io::Result<char>;
+
+    fn next(&mut self) -> Option<io::Result<char>> {
+        let first = match self.next_byte() {
+            Ok(Some(b)) => b,
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let len = match first {
+            0x00..=0x7f => return Some(Ok(first as char)),
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Some(Err(invalid_utf8())),
+        };
+
+        let mut bytes = [first, 0, 0, 0];
+        for byte in bytes.iter_mut().take(len).skip(1) {
+            *byte = match self.next_byte() {
+                Ok(Some(b)) => b,
+                Ok(None) => return Some(Err(invalid_utf8())),
+                Err(e) => return Some(Err(e)),
+            };
+        }
+
+        match str::from_utf8(&bytes[..len]) {
+            Ok(s) => s.chars().next().map(Ok),
+            Err(_) => Some(Err(invalid_utf8())),
         }
-        else { continue; }
-        stripped.push(c);
     }
-    // println!("stripped: {:?}", stripped);
-    stripped
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   "stream did not contain valid UTF-8")
+}
+
+pub fn is_whitespace(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n' || c == '\r'
+}
+
+// NameStartChar in the XML 1.0 specification
+pub fn is_name_start_char(c: char) -> bool {
+    matches!(c,
+             ':' | 'A'..='Z' | '_' | 'a'..='z'
+             | '\u{c0}'..='\u{d6}'
+             | '\u{d8}'..='\u{f6}'
+             | '\u{f8}'..='\u{2ff}'
+             | '\u{370}'..='\u{37d}'
+             | '\u{37f}'..='\u{1fff}'
+             | '\u{200c}'..='\u{200d}'
+             | '\u{2070}'..='\u{218f}'
+             | '\u{2c00}'..='\u{2fef}'
+             | '\u{3001}'..='\u{d7ff}'
+             | '\u{f900}'..='\u{fdcf}'
+             | '\u{fdf0}'..='\u{fffd}'
+             | '\u{10000}'..='\u{effff}')
+}
+
+// NameChar in the XML 1.0 specification
+pub fn is_name_char(c: char) -> bool {
+    is_name_start_char(c)
+        || matches!(c,
+                    '-' | '.' | '0'..='9' | '\u{b7}'
+                    | '\u{300}'..='\u{36f}'
+                    | '\u{203f}'..='\u{2040}')
+}
+
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if is_name_start_char(c) => chars.all(is_name_char),
+        _ => false,
+    }
+}
+
+pub type StrChars<'a> = Map<Chars<'a>, fn(char) -> io::Result<char>>;
+
+// characters of a string, as tokenizer input
+pub fn str_chars(s: &str) -> StrChars<'_> {
+    s.chars().map(Ok)
+}
+
+pub fn tokenize(s: &str) -> Result<Vec<(Token, Position)>, Error> {
+    Tokenizer::new(str_chars(s)).collect()
+}
+
+pub fn string_from_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut string = String::new();
+    File::open(path)?.read_to_string(&mut string)?;
+    Ok(string)
 }
@@ -0,0 +1,107 @@
+use std::collections::VecDeque;
+use std::slice;
+
+use super::nodes::*;
+
+// an element being entered or left
+// during a depth-first traversal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge<'a> {
+    Start(&'a Element),
+    End(&'a Element),
+}
+
+// depth-first traversal of an element and
+// its descendants, see Element::traverse
+pub struct Traverse<'a> {
+    root: Option<&'a Element>,
+    // open elements and the children left to visit
+    stack: Vec<(&'a Element, slice::Iter<'a, Node>)>,
+}
+
+impl<'a> Traverse<'a> {
+    pub fn new(root: &'a Element) -> Traverse<'a> {
+        Traverse {
+            root: Some(root),
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Iterator for Traverse<'a> {
+    type Item = Edge<'a>;
+
+    fn next(&mut self) -> Option<Edge<'a>> {
+        if let Some(root) = self.root.take() {
+            self.stack.push((root, root.children.iter()));
+            return Some(Edge::Start(root));
+        }
+
+        let (element, child) = {
+            let top = self.stack.last_mut()?;
+            (top.0, top.1.find_map(Node::as_element))
+        };
+        match child {
+            Some(child) => {
+                self.stack.push((child, child.children.iter()));
+                Some(Edge::Start(child))
+            }
+            None => {
+                self.stack.pop();
+                Some(Edge::End(element))
+            }
+        }
+    }
+}
+
+// descendants in document order, see Element::descendants
+pub struct Descendants<'a> {
+    traverse: Traverse<'a>,
+}
+
+impl<'a> Descendants<'a> {
+    pub fn new(root: &'a Element) -> Descendants<'a> {
+        let mut traverse = Traverse::new(root);
+        // the root itself isn't a descendant
+        traverse.next();
+        Descendants {
+            traverse,
+        }
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Element;
+
+    fn next(&mut self) -> Option<&'a Element> {
+        loop {
+            match self.traverse.next()? {
+                Edge::Start(e) => return Some(e),
+                Edge::End(_) => {}
+            }
+        }
+    }
+}
+
+// descendants level by level, see Element::breadth_first
+pub struct BreadthFirst<'a> {
+    queue: VecDeque<&'a Element>,
+}
+
+impl<'a> BreadthFirst<'a> {
+    pub fn new(root: &'a Element) -> BreadthFirst<'a> {
+        BreadthFirst {
+            queue: root.children().collect(),
+        }
+    }
+}
+
+impl<'a> Iterator for BreadthFirst<'a> {
+    type Item = &'a Element;
+
+    fn next(&mut self) -> Option<&'a Element> {
+        let e = self.queue.pop_front()?;
+        self.queue.extend(e.children());
+        Some(e)
+    }
+}
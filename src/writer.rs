@@ -0,0 +1,587 @@
+use std::cmp;
+use std::io::{self, Write};
+use std::slice;
+
+use super::namespace::{Namespaces, QName, XMLNS_NAMESPACE};
+use super::nodes::*;
+use super::tokenizer::is_name;
+use super::Document;
+
+pub const XML_DECLARATION: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Newline {
+    Lf,
+    CrLf,
+}
+
+impl Newline {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Newline::Lf => "\n",
+            Newline::CrLf => "\r\n",
+        }
+    }
+}
+
+// options controlling how documents are written
+#[derive(Clone, Debug)]
+pub struct WriterConfig {
+    // string written once per nesting level
+    pub indent: String,
+    pub newline: Newline,
+    // start tags longer than this are written
+    // with one attribute per line
+    pub line_width: Option<usize>,
+    // elements with more attributes than this are written
+    // with one attribute per line
+    pub attributes_per_line: Option<usize>,
+    // sort attributes by name instead of
+    // keeping document order
+    pub sort_attributes: bool,
+    // write every element without content as <a/>,
+    // not only the ones that were empty when parsed
+    pub collapse_empty: bool,
+    // write text content exactly as stored, without
+    // adding or trimming any whitespace around it
+    pub preserve_text: bool,
+    // whether to start with an XML declaration
+    pub write_declaration: bool,
+    // declaration written instead of the one the
+    // document has, or XML_DECLARATION without either,
+    // an encoding in it is always written as UTF-8
+    pub declaration: Option<Declaration>,
+}
+
+impl Default for WriterConfig {
+    fn default() -> WriterConfig {
+        WriterConfig {
+            indent: "\t".into(),
+            newline: Newline::Lf,
+            line_width: None,
+            attributes_per_line: None,
+            sort_attributes: false,
+            collapse_empty: false,
+            preserve_text: true,
+            write_declaration: true,
+            declaration: None,
+        }
+    }
+}
+
+// escapes character data so it can be
+// written between tags
+pub fn escape_text(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            // would be read back as a newline otherwise
+            '\r' => escaped.push_str("&#13;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// escapes an attribute value so it can be
+// written between double quotes
+pub fn escape_attribute(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' => escaped.push_str("&#9;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\r' => escaped.push_str("&#13;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// text nodes that only contain whitespace are dropped
+// when whitespace isn't preserved
+fn is_blank(n: &Node, preserve: bool) -> bool {
+    match *n {
+        Node::Text(ref t) => t.is_empty() || (!preserve && t.trim().is_empty()),
+        _ => false,
+    }
+}
+
+// the prefix of an xmlns or xmlns:prefix attribute,
+// "" for the default namespace
+fn declared_prefix(name: &str) -> Option<&str> {
+    if name == "xmlns" {
+        return Some("");
+    }
+    name.strip_prefix("xmlns:")
+}
+
+// error for nodes and names that can't be written as well-formed XML
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn declaration(prefix: &str, uri: &str) -> Attribute {
+    if prefix.is_empty() {
+        return Attribute::new("xmlns", uri);
+    }
+    Attribute::new(&format!("xmlns:{}", prefix), uri)
+}
+
+fn qualified_name(prefix: &str, q: &QName) -> String {
+    if prefix.is_empty() {
+        return q.get_local_name().to_string();
+    }
+    format!("{}:{}", prefix, q.get_local_name())
+}
+
+// an element whose start tag was written, with
+// how its content and end tag are written
+struct OpenElement<'e> {
+    name: String,
+    // children that are left to write
+    children: slice::Iter<'e, Node>,
+    level: usize,
+    inline: bool,
+    space: bool,
+    preserve: bool,
+    keep_inline: bool,
+}
+
+pub struct Writer<'a, W: Write> {
+    w: W,
+    config: &'a WriterConfig,
+    // prefixes declared by the elements being written
+    namespaces: Namespaces,
+    // for each element being written, whether its default namespace
+    // was declared by an xmlns attribute of an element without a
+    // namespace URI, unqualified names are then left in it
+    literal_default: Vec<bool>,
+}
+
+impl<'a, W: Write> Writer<'a, W> {
+    pub fn new(w: W, config: &'a WriterConfig) -> Writer<'a, W> {
+        Writer {
+            w,
+            config,
+            namespaces: Namespaces::new(),
+            literal_default: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    pub fn write_document(&mut self, d: &Document) -> io::Result<()> {
+        if self.config.write_declaration {
+            match self.config.declaration.as_ref().or(d.declaration()) {
+                Some(declaration) => {
+                    // documents are always written as UTF-8,
+                    // whatever encoding they were read from
+                    let mut declaration = declaration.clone();
+                    if declaration.get_encoding().is_some() {
+                        declaration.set_encoding(Some("UTF-8"));
+                    }
+                    self.w.write_all(declaration.print().as_bytes())?
+                }
+                None => self.w.write_all(XML_DECLARATION.as_bytes())?,
+            }
+            self.newline()?;
+        }
+        // the doctype keeps its place among the top
+        // level nodes, as long as it is before the root
+        let nodes = d.children_nodes();
+        let root = nodes.iter()
+            .position(|n| n.as_element().is_some())
+            .unwrap_or(nodes.len());
+        let doctype_index = cmp::min(d.doctype_index(), root);
+        for (i, n) in nodes.iter().enumerate() {
+            if i == doctype_index {
+                self.write_doctype(d)?;
+            }
+            // text can't appear outside the root element
+            if n.as_text().is_none() {
+                self.write_node(n, 0, false, false)?;
+            }
+        }
+        if doctype_index == nodes.len() {
+            self.write_doctype(d)?;
+        }
+        Ok(())
+    }
+
+    fn write_doctype(&mut self, d: &Document) -> io::Result<()> {
+        if let Some(doctype) = d.doctype() {
+            self.w.write_all(doctype.print().as_bytes())?;
+            self.newline()?;
+        }
+        Ok(())
+    }
+
+    pub fn write_element(&mut self,
+                         e: &Element,
+                         level: usize) -> io::Result<()> {
+        self.write_element_at(e, level, false, false)
+    }
+
+    // writes an element and its content, inline elements are written
+    // without any indentation or newlines, space is whether the
+    // parent is inside xml:space="preserve", the open elements are
+    // kept on a stack so deep documents can't overflow the call stack
+    fn write_element_at(&mut self,
+                        e: &Element,
+                        level: usize,
+                        inline: bool,
+                        space: bool) -> io::Result<()> {
+        let mut stack: Vec<OpenElement> = Vec::new();
+        stack.extend(self.start_element(e, level, inline, space)?);
+        while let Some(top) = stack.last_mut() {
+            let n = match top.children.next() {
+                Some(n) => n,
+                None => {
+                    if let Some(open) = stack.pop() {
+                        self.end_element(&open)?;
+                    }
+                    continue;
+                }
+            };
+            if !top.keep_inline && is_blank(n, top.preserve) {
+                continue;
+            }
+            let (level, inline) = if top.keep_inline { (0, true) } else { (top.level + 1, false) };
+            let space = top.space;
+            match *n {
+                Node::Element(ref e) => stack.extend(self.start_element(e, level, inline, space)?),
+                _ => self.write_node(n, level, inline, space)?,
+            }
+        }
+        Ok(())
+    }
+
+    // writes the start tag of e, returns None if
+    // it was written as an empty element tag
+    fn start_element<'e>(&mut self,
+                         e: &'e Element,
+                         level: usize,
+                         inline: bool,
+                         space: bool) -> io::Result<Option<OpenElement<'e>>> {
+        self.namespaces.push_scope();
+        let (name, attributes) = self.namespace_names(e)?;
+        let mut attributes: Vec<&Attribute> = attributes.iter().collect();
+        if self.config.sort_attributes {
+            attributes.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+        }
+
+        // xml:space is inherited by the content, like when reading
+        let space = match e.get_attribute("xml:space") {
+            Some(a) => a.get_value() == "preserve",
+            None => space,
+        };
+        // inline content is always written exactly as stored
+        let preserve = self.config.preserve_text || inline || space;
+        let has_text = e.children.iter().any(|n| n.as_text().is_some());
+        let has_content = e.children.iter().any(|n| !is_blank(n, preserve));
+        let self_closing = !has_content
+            && (e.is_empty() || self.config.collapse_empty);
+
+        if !inline {
+            self.indent(level)?;
+        }
+        write!(self.w, "<{}", name)?;
+        if !inline && self.wrap_attributes(&name, &attributes, level, self_closing) {
+            for a in &attributes {
+                self.newline()?;
+                self.indent(level + 1)?;
+                self.w.write_all(a.print().as_bytes())?;
+            }
+        }
+        else {
+            for a in &attributes {
+                write!(self.w, " {}", a.print())?;
+            }
+        }
+
+        if self_closing {
+            self.w.write_all(b"/>")?;
+            self.pop_scope();
+            if !inline {
+                self.newline()?;
+            }
+            return Ok(None);
+        }
+
+        self.w.write_all(b">")?;
+        // text is kept on the same line as its tags, so that
+        // no whitespace gets added to it, and none is added
+        // anywhere inside xml:space="preserve"
+        let keep_inline = !has_content || (preserve && has_text) || space;
+        if !keep_inline {
+            self.newline()?;
+        }
+        Ok(Some(OpenElement {
+            name,
+            children: e.children.iter(),
+            level,
+            inline,
+            space,
+            preserve,
+            keep_inline,
+        }))
+    }
+
+    fn end_element(&mut self, open: &OpenElement) -> io::Result<()> {
+        if !open.keep_inline {
+            self.indent(open.level)?;
+        }
+        write!(self.w, "</{}>", open.name)?;
+        self.pop_scope();
+        if open.inline {
+            return Ok(());
+        }
+        self.newline()
+    }
+
+    fn write_node(&mut self,
+                  n: &Node,
+                  level: usize,
+                  inline: bool,
+                  space: bool) -> io::Result<()> {
+        if let Node::Element(ref e) = *n {
+            return self.write_element_at(e, level, inline, space);
+        }
+
+        if !inline {
+            self.indent(level)?;
+        }
+        match *n {
+            Node::Text(ref t) => {
+                if self.config.preserve_text || inline {
+                    self.w.write_all(escape_text(t).as_bytes())?;
+                }
+                else {
+                    self.w.write_all(escape_text(t.trim()).as_bytes())?;
+                }
+            }
+            Node::Comment(ref c) => {
+                // the comment would end early or not at all
+                if c.contains("--") || c.ends_with('-') {
+                    return Err(invalid_input(
+                        format!("Comment can't contain '--' or end with '-': {:?}", c)));
+                }
+                write!(self.w, "<!--{}-->", c)?
+            }
+            Node::CData(ref c) => {
+                // ]]> can't appear inside a section,
+                // so it is split across two of them
+                write!(self.w, "<![CDATA[{}]]>",
+                       c.replace("]]>", "]]]]><![CDATA[>"))?
+            }
+            Node::ProcessingInstruction(ref pi) => {
+                if !is_name(pi.get_target()) {
+                    return Err(invalid_input(
+                        format!("Invalid processing instruction target: {:?}", pi.get_target())));
+                }
+                // the data ends at the first ?>
+                if pi.get_data().contains("?>") {
+                    return Err(invalid_input(
+                        format!("Processing instruction data can't contain '?>': {:?}",
+                                pi.get_data())));
+                }
+                self.w.write_all(pi.print().as_bytes())?
+            }
+            Node::Element(_) => {}
+        }
+
+        if inline {
+            return Ok(());
+        }
+        self.newline()
+    }
+
+    fn wrap_attributes(&self,
+                       name: &str,
+                       attributes: &[&Attribute],
+                       level: usize,
+                       self_closing: bool) -> bool {
+        if attributes.len() < 2 {
+            return false;
+        }
+        if let Some(max) = self.config.attributes_per_line {
+            if attributes.len() > max {
+                return true;
+            }
+        }
+        if let Some(width) = self.config.line_width {
+            let mut length = self.config.indent.chars().count() * level
+                + name.chars().count() + 1;
+            for a in attributes {
+                length += a.print().chars().count() + 1;
+            }
+            length += if self_closing { 2 } else { 1 };
+            if length > width {
+                return true;
+            }
+        }
+        false
+    }
+
+    // returns the name and attributes to write for e, with the
+    // xmlns declarations its namespaces need in the current scope
+    fn namespace_names(&mut self, e: &Element) -> io::Result<(String, Vec<Attribute>)> {
+        // elements without a namespace URI, built by hand or parsed
+        // without namespace_aware, keep their xmlns attributes as they are
+        let literal = e.get_namespace_uri().is_none();
+        let declares_default = e.attributes.iter().any(|a| a.get_name() == "xmlns");
+        let inherited = self.literal_default.last().cloned().unwrap_or(false);
+
+        // declarations already in scope are redundant
+        let mut redundant = Vec::new();
+        for a in &e.attributes {
+            if let Some(prefix) = declared_prefix(a.get_name()) {
+                if !literal && self.namespaces.resolve(prefix).unwrap_or("") == a.get_value() {
+                    redundant.push(a.get_name());
+                    continue;
+                }
+                self.declare(prefix, a.get_value())?;
+            }
+        }
+
+        let mut declarations = Vec::new();
+        let mut used = Vec::new();
+        let name = match e.get_namespace_uri() {
+            Some(uri) => {
+                let prefix = self.bind_prefix(e.get_prefix().unwrap_or(""),
+                                              uri,
+                                              true,
+                                              &used,
+                                              &mut declarations)?;
+                used.push(prefix.clone());
+                qualified_name(&prefix, e.get_qname())
+            }
+            None => {
+                // unprefixed names without a namespace can't be in a
+                // default namespace, unless it was declared literally
+                let undeclare = e.get_prefix().is_none()
+                    && !declares_default
+                    && !inherited
+                    && self.namespaces.resolve("").is_some();
+                if undeclare {
+                    self.declare("", "")?;
+                    declarations.push(declaration("", ""));
+                }
+                e.get_name().to_string()
+            }
+        };
+
+        let mut attributes = Vec::new();
+        for a in &e.attributes {
+            if redundant.contains(&a.get_name()) {
+                continue;
+            }
+            match a.get_namespace_uri() {
+                Some(uri) if uri != XMLNS_NAMESPACE => {
+                    let prefix = self.bind_prefix(a.get_qname().get_prefix().unwrap_or(""),
+                                                  uri,
+                                                  false,
+                                                  &used,
+                                                  &mut declarations)?;
+                    used.push(prefix.clone());
+                    let q = qualified_name(&prefix, a.get_qname());
+                    attributes.push(Attribute::new(&q, a.get_value()));
+                }
+                _ => attributes.push(a.clone()),
+            }
+        }
+
+        let literal_default = if declarations.iter().any(|a| a.get_name() == "xmlns") {
+            false
+        }
+        else if declares_default {
+            literal
+        }
+        else {
+            inherited
+        };
+        self.literal_default.push(literal_default);
+
+        declarations.extend(attributes);
+        Ok((name, declarations))
+    }
+
+    // finds or declares a prefix for uri, preferring the one
+    // the name was created with, attributes can only use
+    // the default namespace by having no namespace
+    fn bind_prefix(&mut self,
+                   prefix: &str,
+                   uri: &str,
+                   element: bool,
+                   used: &[String],
+                   declarations: &mut Vec<Attribute>) -> io::Result<String> {
+        let usable = element || !prefix.is_empty();
+        if usable && self.namespaces.resolve(prefix) == Some(uri) {
+            return Ok(prefix.to_string());
+        }
+        if let Some(p) = self.namespaces.prefix_for(uri, element) {
+            return Ok(p.to_string());
+        }
+
+        // prefixes the element or its other names
+        // rely on can't be bound to something else
+        let taken = |namespaces: &Namespaces, p: &str| {
+            p == "xml" || p == "xmlns"
+                || namespaces.is_declared(p)
+                || used.iter().any(|u| u == p)
+        };
+        let mut prefix = prefix.to_string();
+        if !usable || taken(&self.namespaces, &prefix) {
+            let mut i = 0;
+            loop {
+                prefix = format!("ns{}", i);
+                if self.namespaces.resolve(&prefix).is_none()
+                    && !taken(&self.namespaces, &prefix) {
+                    break;
+                }
+                i += 1;
+            }
+        }
+
+        self.declare(&prefix, uri)?;
+        declarations.push(declaration(&prefix, uri));
+        Ok(prefix)
+    }
+
+    fn pop_scope(&mut self) {
+        self.namespaces.pop_scope();
+        self.literal_default.pop();
+    }
+
+    fn declare(&mut self, prefix: &str, uri: &str) -> io::Result<()> {
+        match self.namespaces.declare(prefix, uri, Default::default()) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(invalid_input(
+                format!("Can't bind namespace prefix {:?} to {:?}", prefix, uri))),
+        }
+    }
+
+    fn indent(&mut self, level: usize) -> io::Result<()> {
+        // nothing to write, deep levels would loop for nothing
+        if self.config.indent.is_empty() {
+            return Ok(());
+        }
+        for _ in 0..level {
+            self.w.write_all(self.config.indent.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn newline(&mut self) -> io::Result<()> {
+        self.w.write_all(self.config.newline.as_str().as_bytes())
+    }
+}